@@ -0,0 +1,5 @@
+fn main() {
+    // Lets the Node.js addon leave the N-API symbols for node to resolve when it loads it.
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
@@ -0,0 +1,77 @@
+#ifndef ONEBRC_H
+#define ONEBRC_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// The stations of one input, from `onebrc_process_file`. Free it with `onebrc_report_free`.
+typedef struct OnebrcReport OnebrcReport;
+
+// One station of an `OnebrcReport`, in degrees rounded to a tenth. `name` belongs to the
+// report and lives as long as it does. A name holding a NUL byte has no C form: it comes out
+// empty, and only `onebrc_report_at` reaches the station.
+typedef struct OnebrcStation {
+  const char *name;
+  double min;
+  double mean;
+  double max;
+  uint32_t count;
+} OnebrcStation;
+
+// Aggregates the measurements at `path`, a text or `.obrc` file, on `threads` workers, or the
+// available cores within the container limits for 0. Returns NULL when it fails, and
+// `onebrc_last_error` says why, a panic included.
+//
+// # Safety
+//
+// `path` is a NUL-terminated string.
+struct OnebrcReport *onebrc_process_file(const char *path, uint32_t threads);
+
+// Stations in `report`.
+//
+// # Safety
+//
+// `report` is from `onebrc_process_file` and not yet freed.
+size_t onebrc_report_len(const struct OnebrcReport *report);
+
+// Lines aggregated into `report`.
+//
+// # Safety
+//
+// `report` is from `onebrc_process_file` and not yet freed.
+uint64_t onebrc_report_lines(const struct OnebrcReport *report);
+
+// Writes the station at `index`, in order of name, to `out`. Returns false when `index` is
+// past the last station.
+//
+// # Safety
+//
+// `report` is from `onebrc_process_file` and not yet freed, and `out` points to an
+// `OnebrcStation`.
+bool onebrc_report_at(const struct OnebrcReport *report, size_t index, struct OnebrcStation *out);
+
+// Writes the station named `name` to `out`. Returns false when the input never mentions it.
+//
+// # Safety
+//
+// `report` is from `onebrc_process_file` and not yet freed, `name` is a NUL-terminated
+// string and `out` points to an `OnebrcStation`.
+bool onebrc_report_get(const struct OnebrcReport *report,
+                       const char *name,
+                       struct OnebrcStation *out);
+
+// Frees `report`; NULL is ignored.
+//
+// # Safety
+//
+// `report` is NULL or from `onebrc_process_file`, and not yet freed.
+void onebrc_report_free(struct OnebrcReport *report);
+
+// Why the last `onebrc_process_file` on this thread returned NULL, or an empty string. The
+// message lives until the next failing call on the thread.
+const char *onebrc_last_error(void);
+
+#endif  /* ONEBRC_H */
@@ -0,0 +1,117 @@
+use std::io::Read;
+use std::path::Path;
+
+use crate::error::{BuildError, Result};
+use crate::malformed::Policy;
+use crate::projection::{InputFormat, Projection};
+use crate::{process_file, process_reader, Engine, MapLayout, Options, Report, MIN_CHUNK_SIZE};
+
+/// A validated pipeline configuration that aggregates any number of files, for library users.
+///
+/// ```no_run
+/// use onebrc::{Aggregator, Engine};
+///
+/// let aggregator = Aggregator::builder()
+///     .threads(16)
+///     .chunk_size(8 << 20)
+///     .engine(Engine::Mmap)
+///     .build()?;
+/// let report = aggregator.process_file("measurements.txt".as_ref())?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Aggregator {
+    options: Options,
+}
+
+impl Aggregator {
+    pub fn builder() -> AggregatorBuilder {
+        AggregatorBuilder::default()
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// `process_file` with these options.
+    pub fn process_file(&self, path: &Path) -> Result<Report> {
+        process_file(path, self.options)
+    }
+
+    /// `process_reader` with these options.
+    pub fn process_reader<R: Read>(&self, reader: R) -> Result<Report> {
+        process_reader(reader, self.options)
+    }
+}
+
+/// Collects the options of an `Aggregator`; every one defaults to what the binary does without
+/// flags. `build` rejects combinations the pipeline cannot run instead of quietly falling back.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AggregatorBuilder {
+    options: Options,
+}
+
+impl AggregatorBuilder {
+    /// Worker threads; the available cores within the container limits when not set.
+    pub fn threads(mut self, threads: usize) -> AggregatorBuilder {
+        self.options.threads = Some(threads);
+        self
+    }
+
+    /// Bytes each worker reads per chunk.
+    pub fn chunk_size(mut self, chunk_size: usize) -> AggregatorBuilder {
+        self.options.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn engine(mut self, engine: Engine) -> AggregatorBuilder {
+        self.options.engine = engine;
+        self
+    }
+
+    pub fn map(mut self, map: MapLayout) -> AggregatorBuilder {
+        self.options.map = map;
+        self
+    }
+
+    /// Reads the station and temperature out of these zero-based columns of wider rows.
+    pub fn projection(mut self, station_column: usize, value_column: usize) -> AggregatorBuilder {
+        self.options.projection = Some(Projection {
+            station_column,
+            value_column,
+            format: InputFormat::Measurements,
+        });
+        self
+    }
+
+    /// Checks every line and skips or stops at the malformed ones.
+    pub fn malformed(mut self, policy: Policy) -> AggregatorBuilder {
+        self.options.malformed = Some(policy);
+        self
+    }
+
+    pub fn build(self) -> std::result::Result<Aggregator, BuildError> {
+        let options = self.options;
+        if options.threads == Some(0) {
+            return Err(BuildError::NoThreads);
+        }
+        if options.chunk_size < MIN_CHUNK_SIZE {
+            return Err(BuildError::ChunkTooSmall {
+                chunk_size: options.chunk_size,
+                min: MIN_CHUNK_SIZE,
+            });
+        }
+        if let Some(projection) = options.projection {
+            if projection.station_column == projection.value_column {
+                return Err(BuildError::SameColumn(projection.station_column));
+            }
+            if options.malformed.is_some() {
+                return Err(BuildError::MalformedWithProjection);
+            }
+            if options.map == MapLayout::Dense {
+                return Err(BuildError::DenseWithProjection);
+            }
+        }
+        Ok(Aggregator { options })
+    }
+}
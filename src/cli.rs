@@ -0,0 +1,20 @@
+//! The binary's building blocks: its subcommands, report formats, timing and caching. Built with
+//! the `cli` feature only, and public only because `main.rs` is a crate of its own.
+
+pub mod ab;
+pub mod cache;
+pub mod concat;
+pub mod daemon;
+#[cfg(feature = "duckdb")]
+pub mod duckdb_sink;
+pub mod hooks;
+pub mod inputs;
+pub mod inspect;
+pub mod mapbench;
+pub mod measure;
+pub mod output;
+pub mod priority;
+pub mod redis;
+pub mod result_cache;
+pub mod selftest;
+mod watch;
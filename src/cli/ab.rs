@@ -0,0 +1,274 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
+
+use clap::ValueEnum;
+
+use super::measure::{Baseline, Summary};
+use crate::error;
+use crate::{aggregate_files, log, signals, Config, Engine, MapLayout, StationTemperatures};
+
+/// Differences printed before the rest are only counted.
+const SHOWN_DIFFERENCES: usize = 10;
+
+/// One side of `--ab`: how chunks are read and where the totals go, such as `mmap`, `dense` or
+/// `uring+sharded`. A side that names only one of them gets `read` or `per-worker` for the
+/// other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Strategy {
+    pub engine: Engine,
+    pub map: MapLayout,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Strategy, String> {
+        let mut strategy = Strategy {
+            engine: Engine::Read,
+            map: MapLayout::PerWorker,
+        };
+        let (mut engine, mut map) = (false, false);
+        for part in value.split('+') {
+            if let (Ok(parsed), false) = (Engine::from_str(part, true), engine) {
+                strategy.engine = parsed;
+                engine = true;
+            } else if let (Ok(parsed), false) = (MapLayout::from_str(part, true), map) {
+                strategy.map = parsed;
+                map = true;
+            } else {
+                return Err(format!(
+                    "`{}` in `{}` is neither an --engine nor a --map, or repeats one",
+                    part, value
+                ));
+            }
+        }
+        Ok(strategy)
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |value: Option<clap::builder::PossibleValue>| {
+            value.map_or(String::new(), |value| value.get_name().to_string())
+        };
+        write!(
+            f,
+            "{}+{}",
+            name(self.engine.to_possible_value()),
+            name(self.map.to_possible_value())
+        )
+    }
+}
+
+/// Parses the two strategies of `--ab a,b`.
+pub fn parse_pair(value: &str) -> Result<[Strategy; 2], String> {
+    let Some((a, b)) = value.split_once(',') else {
+        return Err(format!(
+            "`{}` is not two strategies like `read,mmap`",
+            value
+        ));
+    };
+    Ok([a.parse()?, b.parse()?])
+}
+
+/// Aggregates `inputs` with both `strategies`, taking turns for `warmup` untimed and then
+/// `runs` timed runs each, prints their timings and speedups over the naive baseline and compares every run's station totals with
+/// the first one's. Returns whether they all matched exactly; an interrupted comparison
+/// returns early, matching so far.
+pub fn run(
+    inputs: &[PathBuf],
+    config: &Config,
+    strategies: [Strategy; 2],
+    warmup: u32,
+    runs: u32,
+) -> error::Result<bool> {
+    let configs = strategies.map(|strategy| {
+        let mut config = config.clone();
+        config.map = strategy.map;
+        match inputs {
+            [file_path] => config.prepare(file_path, strategy.engine),
+            // As for any run of several inputs, see `aggregate_files`.
+            _ if strategy.engine != Engine::Read || strategy.map == MapLayout::Dense => {
+                log::info!(
+                    "{} serves a single input, falling back to reading chunks into per-worker maps",
+                    strategy
+                );
+                config.map = MapLayout::PerWorker;
+            }
+            _ => {}
+        }
+        config
+    });
+
+    let mut samples = [Vec::new(), Vec::new()];
+    let mut reference: Option<(StationTemperatures, u64)> = None;
+    let mut differences = Vec::new();
+    for run in 0..warmup + runs {
+        for (side, config) in configs.iter().enumerate() {
+            let started = Instant::now();
+            let aggregation = aggregate_files(inputs, config)?;
+            let elapsed = started.elapsed();
+            if aggregation.resume_offset.is_some() || signals::interrupted() {
+                log::info!("Interrupted, comparing no further runs");
+                return Ok(differences.is_empty());
+            }
+            if run >= warmup {
+                samples[side].push(elapsed);
+            }
+            match &reference {
+                None => {
+                    reference = Some((aggregation.station_temperatures, aggregation.total_lines))
+                }
+                Some((stations, lines)) => differ(
+                    (stations, *lines),
+                    (&aggregation.station_temperatures, aggregation.total_lines),
+                    &format!("{} run {}", strategies[side], run + 1),
+                    &mut differences,
+                ),
+            }
+        }
+    }
+
+    let baseline = Baseline::measure(inputs).unwrap_or_else(|err| {
+        log::info!("Unable to time the baseline: {}", err);
+        None
+    });
+    println!(
+        "{:<20} {:>12} {:>12} {:>12} {:>6} {:>8} {:>8}",
+        "strategy", "min", "median", "stddev", "runs", "GB/s", "speedup"
+    );
+    for (strategy, samples) in strategies.iter().zip(&samples) {
+        let summary = Summary::new(samples);
+        let (throughput, speedup) = match &baseline {
+            Some(baseline) => (
+                format!("{:.3}", baseline.throughput(summary.median)),
+                format!("{:.1}x", baseline.speedup(summary.median)),
+            ),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        println!(
+            "{:<20} {:>12} {:>12} {:>12} {:>6} {:>8} {:>8}",
+            strategy.to_string(),
+            format!("{:.1?}", summary.min),
+            format!("{:.1?}", summary.median),
+            format!("{:.1?}", summary.stddev),
+            summary.runs,
+            throughput,
+            speedup
+        );
+    }
+    if let Some(baseline) = &baseline {
+        println!(
+            "{:<20} {:>12} {:>12} {:>12} {:>6} {:>8} {:>8}",
+            "naive baseline",
+            "-",
+            "-",
+            "-",
+            1,
+            format!("{:.3}", baseline.own_throughput()),
+            "1.0x"
+        );
+    }
+
+    let (stations, lines) = reference.expect("at least one run");
+    if differences.is_empty() {
+        eprintln!(
+            "Results match: {} lines, {} stations",
+            lines,
+            stations.len()
+        );
+        return Ok(true);
+    }
+    eprintln!("Results differ in {} places:", differences.len());
+    for difference in differences.iter().take(SHOWN_DIFFERENCES) {
+        eprintln!("  {}", difference);
+    }
+    if differences.len() > SHOWN_DIFFERENCES {
+        eprintln!("  and {} more", differences.len() - SHOWN_DIFFERENCES);
+    }
+    Ok(false)
+}
+
+/// Adds what tells `actual` apart from `expected` to `differences`, every line naming `run`.
+fn differ(
+    expected: (&StationTemperatures, u64),
+    actual: (&StationTemperatures, u64),
+    run: &str,
+    differences: &mut Vec<String>,
+) {
+    if expected.1 != actual.1 {
+        differences.push(format!(
+            "{}: {} lines instead of {}",
+            run, actual.1, expected.1
+        ));
+    }
+    let mut names = expected.0.keys().chain(actual.0.keys()).collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    for key in names {
+        let name = crate::station_name(key);
+        match (expected.0.get(key), actual.0.get(key)) {
+            (Some(expected), Some(actual)) => {
+                let figures = |data: &crate::WeatherData| {
+                    (
+                        data.min_temperature,
+                        data.max_temperature,
+                        data.total_temperature,
+                        data.count,
+                    )
+                };
+                if figures(expected) != figures(actual) {
+                    differences.push(format!(
+                        "{}: {:?} has min/max/total/count {:?} instead of {:?}",
+                        run,
+                        name,
+                        figures(actual),
+                        figures(expected)
+                    ));
+                }
+            }
+            (Some(_), None) => differences.push(format!("{}: {:?} is missing", run, name)),
+            (None, Some(_)) => differences.push(format!("{}: {:?} is extra", run, name)),
+            (None, None) => unreachable!("every name is in one of them"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ab_strategies_parse_and_agree() {
+        let [a, b] = parse_pair("read,uring+sharded").unwrap();
+        assert_eq!((a.engine, a.map), (Engine::Read, MapLayout::PerWorker));
+        assert_eq!((b.engine, b.map), (Engine::Uring, MapLayout::Sharded));
+        assert_eq!(b.to_string(), "uring+sharded");
+        assert_eq!(parse_pair("dense,mmap").unwrap()[0].map, MapLayout::Dense);
+        assert!(parse_pair("read").is_err());
+        assert!(parse_pair("read,mmap+uring").is_err());
+        assert!(parse_pair("read,bogus").is_err());
+
+        let path = std::env::temp_dir().join(format!("onebrc-ab-test-{}", std::process::id()));
+        let rows = (0..5000)
+            .map(|row| format!("Station{};{}.{}\n", row % 40, row % 90 - 45, row % 10))
+            .collect::<String>();
+        std::fs::write(&path, rows).unwrap();
+        let config = Config {
+            chunk_size: 4096,
+            ..Config::default()
+        };
+        let matched = run(
+            std::slice::from_ref(&path),
+            &config,
+            parse_pair("read+sharded,mmap+dense").unwrap(),
+            1,
+            2,
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matched.unwrap());
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::path::Path;
+
+/// Fraction of `path` currently held in the page cache, `None` where that cannot be queried.
+#[cfg(target_os = "linux")]
+pub fn resident_fraction(path: &Path) -> io::Result<Option<f64>> {
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+    use std::ptr;
+
+    let file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    if len == 0 {
+        return Ok(Some(1.0));
+    }
+
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let mut pages = vec![0u8; len.div_ceil(page_size)];
+    // SAFETY: the mapping is only handed to mincore, which never touches the pages themselves.
+    unsafe {
+        let mapping = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        if mapping == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let result = libc::mincore(mapping, len, pages.as_mut_ptr());
+        libc::munmap(mapping, len);
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let resident = pages.iter().filter(|&&page| page & 1 == 1).count();
+    Ok(Some(resident as f64 / pages.len() as f64))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn resident_fraction(_path: &Path) -> io::Result<Option<f64>> {
+    Ok(None)
+}
+
+/// Evicts `path` from the page cache so the next read comes from storage. Only this file is
+/// affected, so no privileges are needed.
+#[cfg(target_os = "linux")]
+pub fn drop_cached(path: &Path) -> io::Result<()> {
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+
+    let file = File::open(path)?;
+    // Dirty pages survive DONTNEED, so write them back first.
+    file.sync_data()?;
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_cached(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--drop-caches is only supported on Linux",
+    ))
+}
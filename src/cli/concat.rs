@@ -0,0 +1,202 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::Args;
+
+/// Longest possible line: 100 byte station name, `;`, `-99.9` and `\n`.
+const MAX_LINE_SIZE: usize = 107;
+
+#[derive(Args, Debug)]
+pub struct ConcatArgs {
+    /// Shards to concatenate, in order
+    #[arg(required = true)]
+    pub shards: Vec<PathBuf>,
+
+    /// Path of the merged file
+    #[arg(long)]
+    pub out: PathBuf,
+
+    /// Also validate the first and last line of every shard
+    #[arg(long)]
+    pub check_seams: bool,
+}
+
+pub fn run(args: &ConcatArgs) -> io::Result<()> {
+    // Validate everything up front so a bad shard never leaves a half written file behind.
+    for shard in &args.shards {
+        if same_file(shard, &args.out) {
+            return Err(invalid_data(shard, "is also the output file"));
+        }
+        validate_shard(shard, args.check_seams)?;
+    }
+
+    // Written aside and renamed into place, so a failed run leaves no truncated output either.
+    let file_name = args
+        .out
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let partial = args.out.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let result = File::create(&partial).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        for shard in &args.shards {
+            io::copy(&mut File::open(shard)?, &mut out)?;
+        }
+        out.into_inner()?.sync_all()?;
+        fs::rename(&partial, &args.out)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn validate_shard(shard: &Path, check_seams: bool) -> io::Result<()> {
+    let mut file = File::open(shard)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(());
+    }
+
+    let mut tail = vec![0; (len as usize).min(MAX_LINE_SIZE + 1)];
+    file.seek(SeekFrom::End(-(tail.len() as i64)))?;
+    file.read_exact(&mut tail)?;
+    if tail.last() != Some(&b'\n') {
+        return Err(invalid_data(shard, "does not end with a newline"));
+    }
+
+    if !check_seams {
+        return Ok(());
+    }
+
+    let tail = &tail[..tail.len() - 1];
+    let last_line = match tail.iter().rposition(|&b| b == b'\n') {
+        Some(index) => &tail[index + 1..],
+        None if tail.len() as u64 == len - 1 => tail,
+        None => return Err(invalid_data(shard, "has an overlong last line")),
+    };
+    if !is_valid_line(last_line) {
+        return Err(invalid_line(shard, "last", last_line));
+    }
+
+    let mut head = vec![0; (len as usize).min(MAX_LINE_SIZE)];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut head)?;
+    let first_line = match head.iter().position(|&b| b == b'\n') {
+        Some(index) => &head[..index],
+        None => return Err(invalid_data(shard, "has an overlong first line")),
+    };
+    if !is_valid_line(first_line) {
+        return Err(invalid_line(shard, "first", first_line));
+    }
+
+    Ok(())
+}
+
+/// Checks a line (without its `\n`) against the `<station>;<temperature>` format.
+fn is_valid_line(line: &[u8]) -> bool {
+    let Some(separator) = line.iter().position(|&b| b == b';') else {
+        return false;
+    };
+    let (name, temperature) = (&line[..separator], &line[separator + 1..]);
+    if name.is_empty() || name.len() > 100 || std::str::from_utf8(name).is_err() {
+        return false;
+    }
+
+    let digits = temperature.strip_prefix(b"-").unwrap_or(temperature);
+    match digits {
+        [a, b'.', b] => a.is_ascii_digit() && b.is_ascii_digit(),
+        [a, b, b'.', c] => a.is_ascii_digit() && b.is_ascii_digit() && c.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn invalid_data(shard: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} {}", shard.display(), reason),
+    )
+}
+
+fn invalid_line(shard: &Path, which: &str, line: &[u8]) -> io::Error {
+    let reason = format!(
+        "has a malformed {} line: {:?}",
+        which,
+        String::from_utf8_lossy(line)
+    );
+    invalid_data(shard, &reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concat_checks_every_seam_before_writing() {
+        let dir = std::env::temp_dir().join(format!("onebrc-concat-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let shard = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+        let first = shard("1.txt", "Hamburg;12.0\nBulawayo;8.9\n");
+        let second = shard("2.txt", "Oslo;-3.4\n");
+        let unterminated = shard("3.txt", "Oslo;-3.4\nHamburg;1.0");
+        let cut_first = shard("4.txt", "1.0\nOslo;-3.4\n");
+        let cut_last = shard("5.txt", "Oslo;-3.4\nHamb\n");
+        let out = dir.join("out.txt");
+        let concat = |shards: &[&PathBuf], check_seams| {
+            run(&ConcatArgs {
+                shards: shards.iter().map(|&shard| shard.clone()).collect(),
+                out: out.clone(),
+                check_seams,
+            })
+        };
+
+        concat(&[&first, &second], true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "Hamburg;12.0\nBulawayo;8.9\nOslo;-3.4\n"
+        );
+
+        // A failed run leaves the previous output alone and nothing else behind.
+        let err = concat(&[&first, &unterminated], false).unwrap_err();
+        assert!(
+            err.to_string().contains("does not end with a newline"),
+            "{}",
+            err
+        );
+        let err = concat(&[&first, &cut_first], true).unwrap_err();
+        assert!(err.to_string().contains("malformed first line"), "{}", err);
+        let err = concat(&[&cut_last, &second], true).unwrap_err();
+        assert!(err.to_string().contains("malformed last line"), "{}", err);
+        let err = concat(&[&first, &out], false).unwrap_err();
+        assert!(
+            err.to_string().contains("is also the output file"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "Hamburg;12.0\nBulawayo;8.9\nOslo;-3.4\n"
+        );
+        // Without `--check-seams` only the trailing newlines are checked.
+        concat(&[&cut_first, &cut_last], false).unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,620 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use ahash::AHashMap;
+use clap::{ArgGroup, Args};
+
+use super::redis::{self, Publisher};
+use super::watch::{self, DirectoryWatcher, Readiness};
+use crate::log;
+use crate::{
+    aggregate_file, signals, Aggregation, Config, Key, StationTemperatures, Tenths, WeatherData,
+};
+
+/// Directory inside the spool that files failing to aggregate are moved to.
+const FAILED_DIR: &str = "failed";
+
+/// First field of the snapshot line naming the spool file it was last updated with. Station lines
+/// have five fields rather than four, so a station of that name is still read as one.
+const INGESTED: &[u8] = b"#ingested";
+
+/// Upper bound on how long a Ctrl-C or SIGTERM goes unnoticed while waiting for files.
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("source").required(true).multiple(true).args(["spool", "every"])))]
+pub struct DaemonArgs {
+    /// Directory watched for new measurement files
+    #[arg(long, requires = "archive")]
+    pub spool: Option<PathBuf>,
+
+    /// Directory aggregated files are moved to
+    #[arg(long, requires = "spool")]
+    pub archive: Option<PathBuf>,
+
+    /// Running per-station totals, loaded on start and rewritten after every file
+    #[arg(long, default_value = "onebrc.snapshot")]
+    pub snapshot: PathBuf,
+
+    /// Treat a file as complete once it stopped changing for this many milliseconds
+    #[arg(long, default_value_t = 1000)]
+    pub settle_ms: u64,
+
+    /// Only pick up `<file>` once its producer created `<file>.done`
+    #[arg(long)]
+    pub done_marker: bool,
+
+    /// Rebuild the snapshot from the `--input` set on this interval, e.g. `30m` or `1h`
+    #[arg(long, value_parser = parse_duration, requires = "input")]
+    pub every: Option<Duration>,
+
+    /// Files or directories making up the input set of the scheduled rebuild
+    #[arg(long, requires = "every")]
+    pub input: Vec<PathBuf>,
+
+    /// How long a Ctrl-C or SIGTERM may take to write the snapshot before the daemon exits anyway
+    #[arg(long, value_parser = parse_duration, default_value = "10s")]
+    pub grace_period: Duration,
+
+    /// Publish the totals of the stations every snapshot write changes to Redis, as
+    /// `redis://[[user]:password@]host[:port][/db]`
+    #[arg(long, value_name = "URL")]
+    pub redis_url: Option<redis::Url>,
+
+    /// Prefix of the per-station Redis hashes
+    #[arg(long, default_value = "onebrc:station:", requires = "redis_url")]
+    pub redis_prefix: String,
+
+    /// Also announce every update on this Redis pub/sub channel
+    #[arg(long, value_name = "CHANNEL", requires = "redis_url")]
+    pub redis_channel: Option<String>,
+}
+
+/// Aggregates every file dropped into the spool directory into the snapshot until Ctrl-C or
+/// SIGTERM, which stop it after writing the snapshot once more, within the grace period.
+///
+/// New files are noticed through filesystem notifications and only ingested once complete,
+/// judged either by quiescence or by a `.done` marker.
+///
+/// A file is archived only after the snapshot including it has been written. That snapshot also
+/// names the file, so should the daemon stop in between, the next start archives the file
+/// instead of counting its rows twice.
+///
+/// With `--every`, the snapshot is also periodically rebuilt from scratch over the input set
+/// (for example the archive) and swapped in atomically.
+///
+/// With `--redis-url`, every write of the snapshot is followed by publishing the stations it
+/// changed, starting with all of them.
+pub fn run(args: &DaemonArgs) -> io::Result<()> {
+    signals::install();
+    signals::exit_after_grace_period(args.grace_period);
+
+    let mut snapshot = resume(args)?;
+    log::info!("Loaded {} stations from the snapshot", snapshot.len());
+    let mut publisher = args
+        .redis_url
+        .clone()
+        .map(|url| Publisher::new(url, args.redis_prefix.clone(), args.redis_channel.clone()));
+    if !snapshot.is_empty() {
+        publish(&mut publisher, &snapshot, [], &[], 0);
+    }
+
+    let mut watcher = match (&args.spool, &args.archive) {
+        (Some(spool), Some(archive)) => {
+            fs::create_dir_all(archive)?;
+            let readiness = if args.done_marker {
+                Readiness::DoneMarker
+            } else {
+                Readiness::Quiescent(Duration::from_millis(args.settle_ms))
+            };
+            log::info!("Watching {}", spool.display());
+            Some(DirectoryWatcher::new(spool, readiness)?)
+        }
+        _ => None,
+    };
+    let mut next_rebuild = args.every.map(|_| Instant::now());
+
+    while !signals::interrupted() {
+        if let (Some(every), Some(at)) = (args.every, next_rebuild) {
+            if Instant::now() >= at {
+                if let Err(err) = rebuild(args, &mut snapshot, &mut publisher) {
+                    log::info!(
+                        "Unable to rebuild the snapshot: {}, keeping the current one",
+                        err
+                    );
+                }
+                next_rebuild = Some(at + every);
+            }
+        }
+
+        match &mut watcher {
+            Some(watcher) => {
+                for path in watcher.ready(TICK)? {
+                    if signals::interrupted() {
+                        break;
+                    }
+                    ingest(&path, args, &mut snapshot, &mut publisher)?;
+                }
+            }
+            None => thread::sleep(TICK),
+        }
+    }
+
+    // Dropping the watcher on return stops the notifications.
+    save_snapshot(&args.snapshot, &snapshot, None)?;
+    log::info!("Stopped with {} stations in the snapshot", snapshot.len());
+    Ok(())
+}
+
+/// Loads the snapshot and archives the spool file it names, if that is still in the spool.
+fn resume(args: &DaemonArgs) -> io::Result<StationTemperatures> {
+    let (snapshot, ingested) = load_snapshot(&args.snapshot)?;
+    if let (Some(spool), Some(ingested)) = (&args.spool, ingested) {
+        for entry in fs::read_dir(spool)? {
+            let path = entry?.path();
+            if Ingested::of(&path).is_ok_and(|file| file == ingested) {
+                log::info!(
+                    "{} is already in the snapshot, archiving it",
+                    path.display()
+                );
+                archive(&path, args)?;
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Re-aggregates the whole input set and replaces the snapshot with the result.
+fn rebuild(
+    args: &DaemonArgs,
+    snapshot: &mut StationTemperatures,
+    publisher: &mut Option<Publisher>,
+) -> io::Result<()> {
+    let started = Instant::now();
+    let files = input_files(&args.input)?;
+
+    let mut rebuilt: StationTemperatures = AHashMap::with_capacity(10_000);
+    let mut total_lines = 0u64;
+    for path in &files {
+        let aggregation = aggregate_file(path, &Config::default())?;
+        if aggregation.resume_offset.is_some() {
+            // Interrupted: keep serving the previous snapshot.
+            return Ok(());
+        }
+        merge_into(&mut rebuilt, &aggregation.station_temperatures);
+        total_lines += aggregation.total_lines;
+    }
+
+    save_snapshot(&args.snapshot, &rebuilt, None)?;
+    let removed: Vec<Key> = snapshot
+        .keys()
+        .filter(|key| !rebuilt.contains_key(*key))
+        .copied()
+        .collect();
+    *snapshot = rebuilt;
+    publish(publisher, snapshot, snapshot.keys(), &removed, total_lines);
+    log::info!(
+        "Rebuilt the snapshot from {} files in {:?}: {} lines, {} stations",
+        files.len(),
+        started.elapsed(),
+        total_lines,
+        snapshot.len()
+    );
+    Ok(())
+}
+
+/// The given files plus the regular, non-hidden files directly inside the given directories.
+fn input_files(inputs: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(input)? {
+            let entry = entry?;
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && entry.file_type()?.is_file() {
+                entries.push(entry.path());
+            }
+        }
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+fn ingest(
+    path: &Path,
+    args: &DaemonArgs,
+    snapshot: &mut StationTemperatures,
+    publisher: &mut Option<Publisher>,
+) -> io::Result<()> {
+    let Aggregation {
+        station_temperatures,
+        total_lines,
+        resume_offset,
+        ..
+    } = match aggregate_file(path, &Config::default()) {
+        Ok(aggregation) => aggregation,
+        Err(err) => {
+            // One bad file must not stop the daemon, nor be retried on every start.
+            let failed = path.with_file_name(FAILED_DIR);
+            log::info!(
+                "Unable to ingest {}: {}, moving it to {}",
+                path.display(),
+                err,
+                failed.display()
+            );
+            fs::create_dir_all(&failed)?;
+            move_file(path, &failed.join(path.file_name().unwrap()))?;
+            if args.done_marker {
+                fs::remove_file(watch::marker_path(path))?;
+            }
+            return Ok(());
+        }
+    };
+    if resume_offset.is_some() {
+        // Interrupted half way: keep the file in the spool so it is picked up again next time.
+        return Ok(());
+    }
+
+    merge_into(snapshot, &station_temperatures);
+    save_snapshot(&args.snapshot, snapshot, Some(&Ingested::of(path)?))?;
+    publish(
+        publisher,
+        snapshot,
+        station_temperatures.keys(),
+        &[],
+        total_lines,
+    );
+
+    archive(path, args)?;
+    log::info!(
+        "Ingested {}: {} lines, {} stations in the snapshot",
+        path.display(),
+        total_lines,
+        snapshot.len()
+    );
+    Ok(())
+}
+
+/// Moves an ingested file, and its `.done` marker out of the way, into the archive.
+fn archive(path: &Path, args: &DaemonArgs) -> io::Result<()> {
+    let archive = args.archive.as_deref().expect("spool requires an archive");
+    move_file(path, &archive.join(path.file_name().unwrap()))?;
+    if args.done_marker {
+        match fs::remove_file(watch::marker_path(path)) {
+            // Already removed when a stop came between the two.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// A spool file as the snapshot that took it in records it: its name, length and modification
+/// time, which tell it apart from a later file of the same name.
+#[derive(Debug, PartialEq, Eq)]
+struct Ingested {
+    name: Vec<u8>,
+    len: u64,
+    modified: u128,
+}
+
+impl Ingested {
+    fn of(path: &Path) -> io::Result<Ingested> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+        Ok(Ingested {
+            name: path
+                .file_name()
+                .unwrap_or_default()
+                .as_encoded_bytes()
+                .to_vec(),
+            len: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Publishes to Redis where `--redis-url` asks for it. Failing to is no reason to stop
+/// ingesting: it is logged, and the next update publishes every station.
+fn publish<'a>(
+    publisher: &mut Option<Publisher>,
+    snapshot: &StationTemperatures,
+    changed: impl IntoIterator<Item = &'a Key>,
+    removed: &[Key],
+    lines: u64,
+) {
+    if let Some(publisher) = publisher {
+        if let Err(err) = publisher.publish(snapshot, changed, removed, lines) {
+            log::info!("Unable to publish to Redis: {}", err);
+        }
+    }
+}
+
+fn merge_into(snapshot: &mut StationTemperatures, station_temperatures: &StationTemperatures) {
+    station_temperatures.iter().for_each(|(key, data)| {
+        snapshot
+            .entry(*key)
+            .and_modify(|existing| existing.merge(data))
+            .or_insert(*data);
+    });
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    // The archive may live on another filesystem.
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Reads `<station>;<min>;<max>;<total>;<count>` lines written by `save_snapshot`, with the
+/// temperatures in degrees, and the spool file named by the `#ingested` line first, if any.
+fn load_snapshot(path: &Path) -> io::Result<(StationTemperatures, Option<Ingested>)> {
+    let mut snapshot: StationTemperatures = AHashMap::with_capacity(10_000);
+    let mut ingested = None;
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((snapshot, ingested)),
+        Err(err) => return Err(err),
+    };
+
+    for (index, line) in BufReader::new(file).split(b'\n').enumerate() {
+        let line = line?;
+        // From the right: names from `.obrc` and Parquet inputs may hold a `;`.
+        let mut fields: Vec<&[u8]> = line.rsplitn(5, |&b| b == b';').collect();
+        fields.reverse();
+        if let [INGESTED, len, modified, name] = fields[..] {
+            if index > 0 {
+                return Err(corrupt_snapshot(path, &line));
+            }
+            let number = |field: &[u8]| std::str::from_utf8(field).ok()?.parse().ok();
+            ingested = match (number(len), number(modified), from_hex(name)) {
+                (Some(len), Some(modified), Some(name)) => Some(Ingested {
+                    name,
+                    len: len as u64,
+                    modified,
+                }),
+                _ => return Err(corrupt_snapshot(path, &line)),
+            };
+            continue;
+        }
+        let [name, min, max, total, count] = fields[..] else {
+            return Err(corrupt_snapshot(path, &line));
+        };
+        let temperature = |field: &[u8]| {
+            std::str::from_utf8(field)
+                .ok()
+                .and_then(Tenths::parse)
+                .map(|tenths| tenths.0)
+                .ok_or_else(|| corrupt_snapshot(path, &line))
+        };
+        let count = std::str::from_utf8(count)
+            .ok()
+            .and_then(|count| count.parse::<u32>().ok())
+            .ok_or_else(|| corrupt_snapshot(path, &line))?;
+
+        let key = Key::new(name);
+        let data = WeatherData {
+            min_temperature: temperature(min)? as i32,
+            max_temperature: temperature(max)? as i32,
+            total_temperature: temperature(total)?,
+            count,
+            mean_temperature: 0,
+        };
+        snapshot.insert(key, data);
+    }
+
+    Ok((snapshot, ingested))
+}
+
+/// Atomically replaces the snapshot file with the current totals and the spool file they were
+/// just updated with, whose name is written in hex as it may hold any byte.
+fn save_snapshot(
+    path: &Path,
+    snapshot: &StationTemperatures,
+    ingested: Option<&Ingested>,
+) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut out = BufWriter::new(File::create(&temporary)?);
+    if let Some(ingested) = ingested {
+        out.write_all(INGESTED)?;
+        write!(out, ";{};{};", ingested.len, ingested.modified)?;
+        for byte in &ingested.name {
+            write!(out, "{:02x}", byte)?;
+        }
+        writeln!(out)?;
+    }
+    let mut stations: Vec<_> = snapshot.iter().collect();
+    stations.sort_by(|a, b| a.0.cmp(b.0));
+    for (key, data) in stations {
+        out.write_all(key.as_bytes())?;
+        writeln!(
+            out,
+            ";{};{};{};{}",
+            data.min(),
+            data.max(),
+            Tenths(data.total_temperature),
+            data.count
+        )?;
+    }
+    out.into_inner()?.sync_all()?;
+
+    fs::rename(&temporary, path)
+}
+
+/// Parses durations like `500ms`, `30s`, `15m`, `1h` or `1d`, none of them zero.
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", value))?;
+    if amount == 0 {
+        return Err(format!("duration `{}` must not be zero", value));
+    }
+    let seconds = match unit {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "s" | "" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit in duration `{}`", value)),
+    };
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration `{}`", value))
+}
+
+fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn corrupt_snapshot(path: &Path, line: &[u8]) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{}: malformed snapshot line {:?}",
+            path.display(),
+            String::from_utf8_lossy(line)
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(directory: &Path) -> DaemonArgs {
+        DaemonArgs {
+            spool: Some(directory.join("spool")),
+            archive: Some(directory.join("archive")),
+            snapshot: directory.join("snapshot"),
+            settle_ms: 0,
+            done_marker: true,
+            every: None,
+            input: Vec::new(),
+            grace_period: Duration::from_secs(10),
+            redis_url: None,
+            redis_prefix: String::new(),
+            redis_channel: None,
+        }
+    }
+
+    #[test]
+    fn snapshots_keep_names_with_separators() {
+        let path =
+            std::env::temp_dir().join(format!("onebrc-snapshot-test-{}", std::process::id()));
+        let mut snapshot: StationTemperatures = AHashMap::new();
+        for (name, temperature) in [("Semi;colon", 12.5), ("Trailing;", -3.0), ("Plain", 1.0)] {
+            let tenths = (temperature * 10.0) as i32;
+            snapshot.insert(
+                Key::new(name.as_bytes()),
+                WeatherData {
+                    min_temperature: tenths,
+                    max_temperature: tenths,
+                    total_temperature: tenths as i64,
+                    count: 1,
+                    mean_temperature: 0,
+                },
+            );
+        }
+        save_snapshot(&path, &snapshot, None).unwrap();
+        let reloaded = load_snapshot(&path);
+        fs::remove_file(&path).unwrap();
+
+        let (reloaded, ingested) = reloaded.unwrap();
+        assert!(ingested.is_none());
+        assert_eq!(reloaded.len(), 3);
+        let data = reloaded[&Key::new(b"Semi;colon")];
+        assert_eq!((data.min_temperature, data.count), (125, 1));
+        assert_eq!(reloaded[&Key::new(b"Trailing;")].max_temperature, -30);
+    }
+
+    #[test]
+    fn a_stop_before_archiving_does_not_count_the_file_twice() {
+        let directory =
+            std::env::temp_dir().join(format!("onebrc-daemon-test-{}", std::process::id()));
+        let args = args(&directory);
+        let spool = args.spool.clone().unwrap();
+        fs::create_dir_all(&spool).unwrap();
+        fs::create_dir_all(args.archive.as_ref().unwrap()).unwrap();
+        let file = spool.join("a.txt");
+        fs::write(&file, "Hamburg;12.0\nHamburg;-3.4\n").unwrap();
+        fs::write(watch::marker_path(&file), "").unwrap();
+        // Not named by the snapshot, so left for the watcher.
+        let later = spool.join("b.txt");
+        fs::write(&later, "Oslo;1.0\n").unwrap();
+
+        // What `ingest` does up to the stop: the snapshot with the file in it is written, but
+        // the file is still in the spool.
+        let (mut snapshot, _) = load_snapshot(&args.snapshot).unwrap();
+        let aggregation = aggregate_file(&file, &Config::default()).unwrap();
+        merge_into(&mut snapshot, &aggregation.station_temperatures);
+        save_snapshot(
+            &args.snapshot,
+            &snapshot,
+            Some(&Ingested::of(&file).unwrap()),
+        )
+        .unwrap();
+
+        let mut snapshot = resume(&args).unwrap();
+        let archived = args.archive.as_ref().unwrap().join("a.txt");
+        let (file_left, marker_left, archived_exists) = (
+            file.exists(),
+            watch::marker_path(&file).exists(),
+            archived.exists(),
+        );
+        let (hamburg, later_left) = (snapshot[&Key::new(b"Hamburg")].count, later.exists());
+        // The next file's snapshot no longer names the first one.
+        ingest(&later, &args, &mut snapshot, &mut None).unwrap();
+        let (reloaded, ingested) = load_snapshot(&args.snapshot).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(!file_left && !marker_left && archived_exists);
+        assert_eq!(hamburg, 2);
+        assert!(later_left);
+        assert_eq!(reloaded[&Key::new(b"Hamburg")].count, 2);
+        assert_eq!(reloaded[&Key::new(b"Oslo")].count, 1);
+        assert_eq!(ingested.unwrap().name, b"b.txt");
+    }
+
+    #[test]
+    fn durations_take_their_unit_but_never_zero() {
+        use std::time::Duration;
+
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("0ms").is_err());
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("999999999999999999d").is_err());
+        assert!(parse_duration("99999999999999999999").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use duckdb::{params, Connection};
+
+use crate::{station_name, Key, WeatherData};
+
+/// Metadata stored alongside every station row of a run.
+pub struct Run<'a> {
+    pub started_at: SystemTime,
+    /// The input paths, separated by spaces.
+    pub input: &'a str,
+    pub elapsed: Duration,
+    pub total_lines: u64,
+}
+
+/// Appends one row per station to `table`, creating the table on first use.
+pub fn append(
+    database: &Path,
+    table: &str,
+    run: &Run,
+    stations: &[(&Key, &WeatherData)],
+) -> duckdb::Result<()> {
+    let mut connection = Connection::open(database)?;
+    let table = quote_identifier(table);
+    connection.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            run_id VARCHAR,
+            run_started_at TIMESTAMP,
+            input VARCHAR,
+            elapsed_ms DOUBLE,
+            total_lines BIGINT,
+            station VARCHAR,
+            min DECIMAL(4, 1),
+            mean DECIMAL(4, 1),
+            max DECIMAL(4, 1),
+            count BIGINT
+        )",
+        table
+    ))?;
+
+    let started_at = run
+        .started_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let run_id = format!("{}-{}", started_at.as_millis(), std::process::id());
+    let elapsed_ms = run.elapsed.as_secs_f64() * 1000.0;
+
+    let transaction = connection.transaction()?;
+    {
+        // Temperatures go in as their printed form so the decimals match the text report exactly.
+        let mut insert = transaction.prepare(&format!(
+            "INSERT INTO {} VALUES (?, make_timestamp(?), ?, ?, ?, ?, ?::DECIMAL(4, 1), ?::DECIMAL(4, 1), ?::DECIMAL(4, 1), ?)",
+            table
+        ))?;
+        for (key, data) in stations {
+            insert.execute(params![
+                run_id,
+                started_at.as_micros() as i64,
+                run.input,
+                elapsed_ms,
+                run.total_lines as i64,
+                station_name(key),
+                data.min().to_string(),
+                data.mean().to_string(),
+                data.max().to_string(),
+                data.count as i64,
+            ])?;
+        }
+    }
+    transaction.commit()
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
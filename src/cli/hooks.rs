@@ -0,0 +1,110 @@
+use std::panic;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::log;
+
+/// Exit status of a panicking run: aborting under the release profile, unwinding otherwise.
+#[cfg(panic = "abort")]
+const PANIC_STATUS: i32 = 134;
+#[cfg(not(panic = "abort"))]
+const PANIC_STATUS: i32 = 101;
+
+struct Hooks {
+    on_complete: Option<String>,
+    on_error: Option<String>,
+    input: String,
+    output: String,
+    started: Instant,
+}
+
+static HOOKS: OnceLock<Hooks> = OnceLock::new();
+// A worker panic resurfaces in the main thread on join; only report the first failure.
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Registers the commands run when the process finishes, including when it panics.
+pub fn install(
+    on_complete: Option<String>,
+    on_error: Option<String>,
+    input: String,
+    output: String,
+) {
+    if on_complete.is_none() && on_error.is_none() {
+        return;
+    }
+
+    let _ = HOOKS.set(Hooks {
+        on_complete,
+        on_error,
+        input,
+        output,
+        started: Instant::now(),
+    });
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        run_on_error(PANIC_STATUS, &info.to_string());
+    }));
+}
+
+/// Runs the `--on-complete` command for a successful run.
+pub fn complete() {
+    if let Some(hooks) = HOOKS.get() {
+        if let Some(command) = &hooks.on_complete {
+            run(command, hooks, 0, None);
+        }
+    }
+}
+
+/// Runs the `--on-error` command and exits with `status`.
+pub fn fail(status: i32, error: &str) -> ! {
+    run_on_error(status, error);
+    process::exit(status);
+}
+
+fn run_on_error(status: i32, error: &str) {
+    if FAILED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    if let Some(hooks) = HOOKS.get() {
+        if let Some(command) = &hooks.on_error {
+            run(command, hooks, status, Some(error));
+        }
+    }
+}
+
+fn run(command: &str, hooks: &Hooks, status: i32, error: Option<&str>) {
+    let mut shell = if cfg!(windows) {
+        let mut shell = process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    shell
+        .arg(command)
+        .env("ONEBRC_EXIT_STATUS", status.to_string())
+        .env(
+            "ONEBRC_ELAPSED_SECONDS",
+            format!("{:.3}", hooks.started.elapsed().as_secs_f64()),
+        )
+        .env("ONEBRC_INPUT", &hooks.input)
+        .env("ONEBRC_OUTPUT", &hooks.output);
+    if let Some(error) = error {
+        shell.env("ONEBRC_ERROR", error);
+    }
+
+    match shell.status() {
+        Ok(hook_status) if !hook_status.success() => {
+            log::info!("Hook `{}` exited with {}", command, hook_status)
+        }
+        Ok(_) => {}
+        Err(err) => eprintln!("Unable to run hook `{}`: {}", command, err),
+    }
+}
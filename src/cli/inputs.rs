@@ -0,0 +1,118 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{is_remote, is_stdin};
+
+/// Expands the inputs as given on the command line into the files they name, in order: a glob
+/// such as `data/part-*.txt` into every file it matches and a directory into every file below
+/// it, each sorted by path so a dataset is always read in the same order. Other paths, `-`
+/// included, are kept as they are and checked by whatever opens them.
+///
+/// A directory walk skips names that start with `.` or `_`, the checksums and `_SUCCESS`
+/// markers other tools leave next to their shards, and does not follow symlinks to
+/// directories.
+pub fn expand(inputs: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let start = files.len();
+        if is_glob(input) {
+            for path in matches(input)? {
+                match path.is_dir() {
+                    true => walk(&path, &mut files)?,
+                    false => files.push(path),
+                }
+            }
+        } else if input.is_dir() {
+            walk(input, &mut files)?;
+        } else {
+            files.push(input.clone());
+        }
+        if files.len() == start {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} names no files", input.display()),
+            ));
+        }
+    }
+    Ok(files)
+}
+
+fn is_glob(input: &Path) -> bool {
+    !is_stdin(input)
+        && !is_remote(input)
+        && input
+            .to_str()
+            .is_some_and(|input| input.contains(['*', '?', '[']))
+}
+
+/// The paths `pattern` matches, sorted.
+fn matches(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidInput, err);
+    let pattern = pattern.to_str().unwrap_or_default();
+    let mut paths = glob::glob(pattern)
+        .map_err(|err| invalid(format!("invalid pattern {}: {}", pattern, err)))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::from)?;
+    paths.sort_unstable();
+    Ok(paths)
+}
+
+/// Appends every file below `dir` to `files`, depth first with the entries of every directory
+/// sorted by name.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_unstable_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(['.', '_']))
+        {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_expand_in_path_order() {
+        let dir = std::env::temp_dir().join(format!("onebrc-inputs-test-{}", std::process::id()));
+        for path in [
+            "b/2.txt",
+            "b/1.txt",
+            "a.txt",
+            "c/_SUCCESS",
+            "c/.1.txt.crc",
+            "c/1.txt",
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let expanded = expand(&[
+            dir.join("c"),
+            dir.join("*.txt"),
+            dir.join("b"),
+            PathBuf::from("-"),
+        ]);
+        let missing = expand(&[dir.join("*.gz")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected = ["c/1.txt", "a.txt", "b/1.txt", "b/2.txt"].map(|path| dir.join(path));
+        let mut expanded = expanded.unwrap();
+        assert_eq!(expanded.pop(), Some(PathBuf::from("-")));
+        assert_eq!(expanded, expected);
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use ahash::AHashSet;
+use clap::Args;
+
+use crate::WIDE_ROW_SIZE;
+
+/// Delimiters tried, in order of preference on a tie.
+const DELIMITERS: [u8; 4] = [b';', b',', b'\t', b'|'];
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    /// Delimited file to inspect
+    pub input: PathBuf,
+
+    /// Number of lines sampled from the start of the file
+    #[arg(long, default_value_t = 1000)]
+    pub lines: usize,
+}
+
+/// Samples the start of a delimited file, guesses its layout and prints the flags the
+/// aggregation needs to process it.
+pub fn run(args: &InspectArgs) -> io::Result<()> {
+    let mut sample = Vec::new();
+    File::open(&args.input)?
+        .take((args.lines.max(2) * WIDE_ROW_SIZE) as u64)
+        .read_to_end(&mut sample)?;
+
+    let crlf = sample.windows(2).any(|pair| pair == b"\r\n");
+    let mut lines: Vec<&[u8]> = sample
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .collect();
+    // The sample most likely ends mid line.
+    if lines.len() > 1 && !sample.ends_with(b"\n") {
+        lines.pop();
+    }
+    lines.truncate(args.lines);
+    lines.retain(|line| !line.is_empty());
+    if lines.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no lines to inspect", args.input.display()),
+        ));
+    }
+
+    let delimiter = detect_delimiter(&lines);
+    let rows: Vec<Vec<&[u8]>> = lines
+        .iter()
+        .map(|line| line.split(|&b| b == delimiter).collect())
+        .collect();
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+
+    // A header is a first row that is text where the rows below it are numbers.
+    let is_numeric_column = |rows: &[Vec<&[u8]>], column: usize| {
+        !rows.is_empty()
+            && rows
+                .iter()
+                .all(|row| row.get(column).is_some_and(|field| is_number(field)))
+    };
+    let header = rows.len() > 1
+        && (0..columns).any(|column| {
+            is_numeric_column(&rows[1..], column) && !is_numeric_column(&rows[..1], column)
+        });
+    let data = if header { &rows[1..] } else { &rows[..] };
+
+    let value_columns: Vec<usize> = (0..columns)
+        .filter(|&column| is_numeric_column(data, column))
+        .collect();
+    let station_columns: Vec<usize> = (0..columns)
+        .filter(|&column| !is_numeric_column(data, column))
+        .collect();
+    // Prefer a decimal column for the temperature, and for the station the text column that
+    // repeats the most without being constant (timestamps are unique, flags barely vary).
+    let value_column = value_columns
+        .iter()
+        .copied()
+        .find(|&column| data.iter().all(|row| row[column].contains(&b'.')))
+        .or(value_columns.first().copied());
+    let station_column = station_columns
+        .iter()
+        .copied()
+        .filter(|&column| (2..data.len()).contains(&distinct(data, column)))
+        .min_by_key(|&column| distinct(data, column))
+        .or(station_columns.first().copied());
+
+    println!("Sampled lines: {}", lines.len());
+    println!("Delimiter: {:?}", delimiter as char);
+    println!("Columns: {}", columns);
+    println!("Header: {}", if header { "yes" } else { "no" });
+    println!("Line endings: {}", if crlf { "\\r\\n" } else { "\\n" });
+    println!("Station columns: {:?}", station_columns);
+    println!("Value columns: {:?}", value_columns);
+
+    let (Some(station_column), Some(value_column)) = (station_column, value_column) else {
+        println!("No station/value column pair found");
+        return Ok(());
+    };
+    if delimiter == b',' {
+        // Header names, where they need no shell quoting, read better than indices.
+        let column = |column: usize| {
+            let name = rows[0][column];
+            let name = name.strip_prefix("\u{feff}".as_bytes()).unwrap_or(name);
+            let name = name.strip_prefix(b"\"").unwrap_or(name);
+            let name = name.strip_suffix(b"\"").unwrap_or(name);
+            match std::str::from_utf8(name) {
+                Ok(name)
+                    if header
+                        && name.parse::<usize>().is_err()
+                        && !name.is_empty()
+                        && name
+                            .bytes()
+                            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-') =>
+                {
+                    name.to_string()
+                }
+                _ => column.to_string(),
+            }
+        };
+        println!(
+            "Flags: --input-format csv --station-col {} --temp-col {}",
+            column(station_column),
+            column(value_column)
+        );
+        return Ok(());
+    }
+    if delimiter != b';' {
+        println!(
+            "Only `;` is supported as a delimiter, convert the file first, e.g. with `tr {:?} ';'`",
+            delimiter as char
+        );
+    }
+    let plain = columns == 2 && station_column == 0 && value_column == 1 && !header && !crlf;
+    if plain {
+        println!("Flags: none needed");
+    } else {
+        // The projection path skips header rows and trims the `\r` off the temperature.
+        println!(
+            "Flags: --station-col {} --value-col {}",
+            station_column, value_column
+        );
+    }
+    Ok(())
+}
+
+/// The delimiter splitting every sampled line into the same, largest number of fields.
+fn detect_delimiter(lines: &[&[u8]]) -> u8 {
+    let fields = |line: &[u8], delimiter: u8| line.iter().filter(|&&b| b == delimiter).count();
+    DELIMITERS
+        .iter()
+        .copied()
+        .filter(|&delimiter| {
+            let first = fields(lines[0], delimiter);
+            first > 0 && lines.iter().all(|line| fields(line, delimiter) == first)
+        })
+        .max_by_key(|&delimiter| {
+            (
+                fields(lines[0], delimiter),
+                std::cmp::Reverse(DELIMITERS.iter().position(|&d| d == delimiter)),
+            )
+        })
+        .unwrap_or(b';')
+}
+
+fn is_number(field: &[u8]) -> bool {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|field| field.trim().parse::<f32>().ok())
+        .is_some()
+}
+
+fn distinct(rows: &[Vec<&[u8]>], column: usize) -> usize {
+    rows.iter()
+        .filter_map(|row| row.get(column))
+        .collect::<AHashSet<_>>()
+        .len()
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{BuildHasher, RandomState};
+use std::hint::black_box;
+use std::io::{self, BufRead, BufReader};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rustc_hash::FxBuildHasher;
+
+use crate::log;
+use crate::obrc;
+use crate::projection::{InputFormat, Projection};
+use crate::table::StationTable;
+use crate::{Key, WeatherData};
+
+/// Records captured from the input, about 400MB of them, so the replay never touches storage.
+const CAPTURE_LIMIT: usize = 20_000_000;
+
+/// Captures the (station, temperature) stream of `path` and replays it against each map
+/// implementation, reporting upserts per second and the final table footprint.
+pub fn run(path: &Path, projection: Option<Projection>) -> io::Result<()> {
+    if obrc::is_obrc(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "map-bench replays text input, not .obrc",
+        ));
+    }
+    let projection = projection.unwrap_or(Projection {
+        station_column: 0,
+        value_column: 1,
+        format: InputFormat::Measurements,
+    });
+
+    // Names go into one arena so the replay looks them up by slice, as the parsers do.
+    let mut names = Vec::new();
+    let mut records = Vec::with_capacity(CAPTURE_LIMIT.min(1 << 20));
+    for line in BufReader::with_capacity(1 << 20, File::open(path)?).split(b'\n') {
+        if records.len() == CAPTURE_LIMIT {
+            break;
+        }
+        if let Some((station, temperature)) = projection.parse_line(&line?) {
+            records.push((names.len()..names.len() + station.len(), temperature));
+            names.extend_from_slice(station);
+        }
+    }
+    if records.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no records to replay", path.display()),
+        ));
+    }
+    log::info!("Captured {} records", records.len());
+
+    println!(
+        "{:<16} {:>12} {:>16} {:>10} {:>12}",
+        "map", "time", "upserts/s", "stations", "memory"
+    );
+    report("AHashMap", replay::<ahash::RandomState>(&names, &records));
+    report("FxHashMap", replay::<FxBuildHasher>(&names, &records));
+    report("std HashMap", replay::<RandomState>(&names, &records));
+    report("StationTable", replay_table(&names, &records));
+    Ok(())
+}
+
+struct Replay {
+    elapsed: Duration,
+    upserts: usize,
+    stations: usize,
+    memory: usize,
+}
+
+/// Upserts every record the way `process_buffer` does, into a map with its initial capacity.
+fn replay<S: BuildHasher + Default>(names: &[u8], records: &[(Range<usize>, i32)]) -> Replay {
+    let started = Instant::now();
+    let mut station_temperatures: HashMap<Key, WeatherData, S> =
+        HashMap::with_capacity_and_hasher(1000, S::default());
+    for (station, temperature) in records {
+        let station = &names[station.clone()];
+        let temperature = *temperature;
+        if let Some(data) = station_temperatures.get_mut(station) {
+            data.add_temperature(temperature);
+        } else {
+            station_temperatures.insert(
+                Key::new(station),
+                WeatherData {
+                    total_temperature: temperature as i64,
+                    count: 1,
+                    min_temperature: temperature,
+                    max_temperature: temperature,
+                    mean_temperature: 0,
+                },
+            );
+        }
+    }
+    let elapsed = started.elapsed();
+    let station_temperatures = black_box(station_temperatures);
+
+    // hashbrown keeps one control byte per slot next to the entries.
+    let slot_size = mem::size_of::<(Key, WeatherData)>() + 1;
+    Replay {
+        elapsed,
+        upserts: records.len(),
+        stations: station_temperatures.len(),
+        memory: station_temperatures.capacity() * slot_size,
+    }
+}
+
+/// Upserts every record into the table `process_buffer` uses.
+fn replay_table(names: &[u8], records: &[(Range<usize>, i32)]) -> Replay {
+    let started = Instant::now();
+    let mut table = StationTable::new();
+    for (station, temperature) in records {
+        table.add(&names[station.clone()], *temperature);
+    }
+    let elapsed = started.elapsed();
+    let table = black_box(table);
+
+    Replay {
+        elapsed,
+        upserts: records.len(),
+        stations: table.len(),
+        memory: table.memory(),
+    }
+}
+
+fn report(name: &str, replay: Replay) {
+    println!(
+        "{:<16} {:>12} {:>14.1} M {:>10} {:>9} KiB",
+        name,
+        format!("{:.1?}", replay.elapsed),
+        replay.upserts as f64 / replay.elapsed.as_secs_f64() / 1e6,
+        replay.stations,
+        replay.memory / 1024
+    );
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::compressed::Format;
+use crate::{is_parquet, is_remote, is_stdin};
+
+/// Bytes of the first input the naive baseline aggregates.
+const BASELINE_SAMPLE: u64 = 64 << 20;
+
+/// Wall-clock statistics over the timed runs of a repeated measurement.
+pub struct Summary {
+    pub runs: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub mean: Duration,
+    pub stddev: Duration,
+    /// Runs outside the Tukey fences, 1.5 interquartile ranges beyond the quartiles.
+    pub outliers: usize,
+}
+
+impl Summary {
+    pub fn new(samples: &[Duration]) -> Summary {
+        assert!(!samples.is_empty(), "no samples to summarize");
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+        let seconds: Vec<f64> = sorted.iter().map(Duration::as_secs_f64).collect();
+
+        let mean = seconds.iter().sum::<f64>() / seconds.len() as f64;
+        let variance = if seconds.len() > 1 {
+            seconds.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (seconds.len() - 1) as f64
+        } else {
+            0.0
+        };
+
+        let (q1, q3) = (quantile(&seconds, 0.25), quantile(&seconds, 0.75));
+        let fence = 1.5 * (q3 - q1);
+        let outliers = seconds
+            .iter()
+            .filter(|&&s| s < q1 - fence || s > q3 + fence)
+            .count();
+
+        Summary {
+            runs: samples.len(),
+            min: sorted[0],
+            median: Duration::from_secs_f64(quantile(&seconds, 0.5)),
+            mean: Duration::from_secs_f64(mean),
+            stddev: Duration::from_secs_f64(variance.sqrt()),
+            outliers,
+        }
+    }
+
+    pub fn print(&self) {
+        eprintln!(
+            "Runs: {}, Min: {:.3?}, Median: {:.3?}, Mean: {:.3?}, Stddev: {:.3?}",
+            self.runs, self.min, self.median, self.mean, self.stddev
+        );
+        if self.outliers > 0 {
+            eprintln!(
+                "Outliers: {} of {} runs beyond 1.5 IQR of the quartiles",
+                self.outliers, self.runs
+            );
+        }
+    }
+}
+
+/// Linear interpolation between the closest ranks of sorted samples.
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Throughput of the simplest correct aggregation, a `HashMap<String, _>` of `f64` totals fed
+/// one allocated line at a time, to state every strategy's speed relative to it. It reads a
+/// sample from the start of the first input, after the timed runs warmed the page cache.
+pub struct Baseline {
+    /// Bytes of all the inputs, which each timed run aggregates.
+    pub input_bytes: u64,
+    pub sample_bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Baseline {
+    /// `None` unless every input is a plain local file of `name;value` lines.
+    pub fn measure(inputs: &[PathBuf]) -> io::Result<Option<Baseline>> {
+        let plain = |path: &PathBuf| {
+            !is_stdin(path)
+                && !is_remote(path)
+                && !is_parquet(path)
+                && Format::detect(path) == Format::Plain
+        };
+        if inputs.is_empty() || !inputs.iter().all(plain) {
+            return Ok(None);
+        }
+        let mut input_bytes = 0;
+        for path in inputs {
+            input_bytes += fs::metadata(path)?.len();
+        }
+
+        let started = Instant::now();
+        let mut reader = BufReader::new(File::open(&inputs[0])?.take(BASELINE_SAMPLE));
+        let mut stations: HashMap<String, (f64, f64, f64, u64)> = HashMap::new();
+        let (mut sample_bytes, mut line) = (0, String::new());
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            // The sample ends in the middle of a line, which is left out.
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            let Some((name, value)) = line.trim_end().rsplit_once(';') else {
+                continue;
+            };
+            let Ok(value) = value.parse::<f64>() else {
+                continue;
+            };
+            let entry = stations
+                .entry(name.to_string())
+                .or_insert((f64::MAX, f64::MIN, 0.0, 0));
+            entry.0 = entry.0.min(value);
+            entry.1 = entry.1.max(value);
+            entry.2 += value;
+            entry.3 += 1;
+            sample_bytes += read as u64;
+        }
+        let elapsed = started.elapsed();
+        std::hint::black_box(&stations);
+
+        Ok((!stations.is_empty()).then_some(Baseline {
+            input_bytes,
+            sample_bytes,
+            elapsed,
+        }))
+    }
+
+    /// Gigabytes a second, of the inputs aggregated in `elapsed`.
+    pub fn throughput(&self, elapsed: Duration) -> f64 {
+        self.input_bytes as f64 / elapsed.as_secs_f64() / 1e9
+    }
+
+    /// Gigabytes a second of the baseline itself.
+    pub fn own_throughput(&self) -> f64 {
+        self.sample_bytes as f64 / self.elapsed.as_secs_f64() / 1e9
+    }
+
+    /// How many times faster than the baseline a run taking `elapsed` went, per byte.
+    pub fn speedup(&self, elapsed: Duration) -> f64 {
+        self.throughput(elapsed) / self.own_throughput()
+    }
+
+    /// The baseline's throughput and the speedup of the median run.
+    pub fn print(&self, summary: &Summary) {
+        eprintln!(
+            "Baseline: {:.3} GB/s naive over {} bytes, Median: {:.3} GB/s, {:.1}x the baseline",
+            self.own_throughput(),
+            self.sample_bytes,
+            self.throughput(summary.median),
+            self.speedup(summary.median)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baseline_reads_whole_lines_of_plain_inputs() {
+        let dir = std::env::temp_dir().join(format!("onebrc-baseline-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let plain = dir.join("measurements.txt");
+        fs::write(&plain, "Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4").unwrap();
+        let gzip = dir.join("measurements.txt.gz");
+        fs::write(&gzip, [0x1f, 0x8b, 0, 0]).unwrap();
+        let other = dir.join("readings.csv");
+        fs::write(&other, "city,reading\n").unwrap();
+
+        let baseline = Baseline::measure(std::slice::from_ref(&plain))
+            .unwrap()
+            .unwrap();
+        // The unterminated last line is left out of the sample, not out of the input.
+        assert_eq!(baseline.sample_bytes, 26);
+        assert_eq!(baseline.input_bytes, 38);
+        assert!(Baseline::measure(&[plain.clone(), gzip]).unwrap().is_none());
+        assert!(Baseline::measure(&[other]).unwrap().is_none());
+        assert!(Baseline::measure(&[dir.join("missing.txt")]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1075 @@
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use crate::sketch::{Percentile, Sketches};
+use crate::{round_tenths, Key, Tenths, WeatherData};
+
+/// Report bytes per station to reserve up front, enough for the longest format with a typical
+/// name, so rendering seldom reallocates.
+const STATION_REPORT_SIZE: usize = 128;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One `Station: ..., Min: ..., Mean: ..., Max: ...` line per station
+    Text,
+    /// A `COPY ... FROM STDIN` stream that can be piped straight into psql
+    PgCopy,
+    /// An array of `{station, min, mean, max, count}` objects
+    Json,
+    /// The single `{Abha=-23.0/18.0/59.2, ...}` line the 1BRC reference implementation prints
+    Challenge,
+    /// Aligned columns under a header, for reading in a terminal
+    Table,
+    /// A GitHub Markdown table
+    Md,
+    /// An Arrow IPC file of `station`, `min`, `mean`, `max` and `count` columns, which Polars,
+    /// pandas and DuckDB load without parsing (`arrow` feature)
+    Arrow,
+    /// A Parquet file of the same columns, to archive (`parquet` feature)
+    Parquet,
+}
+
+/// What `--sort-by` orders the report by.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// The station name as printed, in code point order
+    Name,
+    /// The lowest temperature
+    Min,
+    /// The mean temperature
+    Mean,
+    /// The highest temperature
+    Max,
+    /// The number of measurements
+    Count,
+}
+
+/// Reorders `stations`, which `finish` returns by name, by `by`, highest first when
+/// `descending`. Stations that tie stay in name order.
+pub fn sort(stations: &mut [(&Key, &WeatherData)], by: SortBy, descending: bool) {
+    let value = |data: &WeatherData| match by {
+        SortBy::Name => 0,
+        SortBy::Min => data.min().0,
+        SortBy::Mean => data.mean().0,
+        SortBy::Max => data.max().0,
+        SortBy::Count => data.count as i64,
+    };
+    match (by, descending) {
+        (SortBy::Name, false) => {}
+        (SortBy::Name, true) => stations.reverse(),
+        (_, false) => stations.sort_by_key(|(_, data)| value(data)),
+        (_, true) => stations.sort_by_key(|(_, data)| std::cmp::Reverse(value(data))),
+    }
+}
+
+/// Dataset-wide figures for `--summary`, over every station of the run.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub rows: u64,
+    pub stations: usize,
+    /// The lowest temperature and the first station in report order to have it.
+    pub coldest: Option<(String, Tenths)>,
+    /// The highest temperature and the first station in report order to have it.
+    pub hottest: Option<(String, Tenths)>,
+    /// Rounded the way the per-station means are.
+    pub mean: Option<Tenths>,
+}
+
+impl Summary {
+    pub fn of(stations: &[(&Key, &WeatherData)]) -> Summary {
+        let coldest = stations.iter().min_by_key(|(_, data)| data.min_temperature);
+        let hottest = stations
+            .iter()
+            .rev()
+            .max_by_key(|(_, data)| data.max_temperature);
+        let rows: u64 = stations.iter().map(|(_, data)| data.count as u64).sum();
+        let total: i64 = stations
+            .iter()
+            .map(|(_, data)| data.total_temperature)
+            .sum();
+        Summary {
+            rows,
+            stations: stations.len(),
+            coldest: coldest.map(|(key, data)| (station_name(key).into_owned(), data.min())),
+            hottest: hottest.map(|(key, data)| (station_name(key).into_owned(), data.max())),
+            mean: (rows > 0).then(|| Tenths(round_tenths(total, rows as i64))),
+        }
+    }
+
+    /// Prints the summary line, with the throughput over `elapsed` when `bytes` were read.
+    pub fn print(&self, bytes: u64, elapsed: Duration) {
+        let extreme = |extreme: &Option<(String, Tenths)>| match extreme {
+            Some((name, temperature)) => format!("{:.1} ({})", temperature, name),
+            None => "-".to_string(),
+        };
+        let mean = match self.mean {
+            Some(mean) => format!("{:.1}", mean),
+            None => "-".to_string(),
+        };
+        let mut line = format!(
+            "Summary: {} rows, {} stations, min {}, max {}, mean {}",
+            self.rows,
+            self.stations,
+            extreme(&self.coldest),
+            extreme(&self.hottest),
+            mean
+        );
+        if bytes > 0 {
+            let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+            line.push_str(&format!(
+                ", {} bytes at {:.2} GB/s",
+                bytes,
+                bytes as f64 / seconds / 1e9
+            ));
+        }
+        eprintln!("{}", line);
+    }
+}
+
+/// Keeps the `top` stations with the highest `--sort-by` value and the `bottom` ones with the
+/// lowest, of `stations` as `sort` left them, in that order. Either left out keeps none from
+/// its end, both left out keep every station.
+pub fn keep_extremes(
+    stations: &mut Vec<(&Key, &WeatherData)>,
+    descending: bool,
+    top: Option<usize>,
+    bottom: Option<usize>,
+) {
+    if top.is_none() && bottom.is_none() {
+        return;
+    }
+    let (first, last) = match descending {
+        false => (bottom.unwrap_or(0), top.unwrap_or(0)),
+        true => (top.unwrap_or(0), bottom.unwrap_or(0)),
+    };
+    if first.saturating_add(last) < stations.len() {
+        stations.drain(first..stations.len() - last);
+    }
+}
+
+/// How the columns of the parquet format are compressed.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Stored as written
+    None,
+    /// Fast to write and read, and what most tools default to
+    Snappy,
+    /// Smaller, and readable by the oldest tools
+    Gzip,
+    /// The raw LZ4 codec, fastest to read
+    Lz4,
+    /// Smallest at a speed close to snappy
+    Zstd,
+}
+
+pub struct Options<'a> {
+    pub format: Format,
+    pub pg_table: &'a str,
+    pub compression: Compression,
+    /// Add the number of measurements to the formats that leave it out, for `--with-count`.
+    pub with_count: bool,
+    /// Add these columns to every format but the challenge line, for `--stats p50,p99`.
+    pub percentiles: Option<Percentiles<'a>>,
+    /// The input each station was read from, in report order, for `--tag-by-source`. Every
+    /// format but the challenge line puts it in a `source` column ahead of the station.
+    pub sources: Option<&'a [String]>,
+}
+
+/// The percentiles a report shows, and the digests they come from.
+#[derive(Clone, Copy)]
+pub struct Percentiles<'a> {
+    pub wanted: &'a [Percentile],
+    pub sketches: &'a Sketches,
+}
+
+impl Percentiles<'_> {
+    /// The wanted percentiles of `key`, `None` where the station has no digest.
+    fn of(&self, key: &Key) -> Vec<Option<Tenths>> {
+        self.wanted
+            .iter()
+            .map(|&percentile| self.sketches.percentile(key, percentile))
+            .collect()
+    }
+}
+
+/// The percentile columns of `options`, none without `--stats pNN`.
+fn wanted<'a>(options: &Options<'a>) -> &'a [Percentile] {
+    options
+        .percentiles
+        .map_or(&[], |percentiles| percentiles.wanted)
+}
+
+/// The source of the station at `index` in the report, none without `--tag-by-source`.
+fn source_of<'a>(options: &Options<'a>, index: usize) -> Option<&'a str> {
+    options.sources.map(|sources| sources[index].as_str())
+}
+
+/// The percentiles of `key` the report shows, in column order.
+fn percentiles_of(options: &Options, key: &Key) -> Vec<Option<Tenths>> {
+    options
+        .percentiles
+        .map_or(Vec::new(), |percentiles| percentiles.of(key))
+}
+
+pub fn write_report(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    match options.format {
+        Format::Text => write_text(out, options, stations),
+        Format::PgCopy => write_pg_copy(out, options, stations),
+        Format::Json => write_json(out, options, stations),
+        Format::Challenge => write_challenge(out, options.with_count, stations),
+        Format::Table => write_table(out, options, stations),
+        Format::Md => write_markdown(out, options, stations),
+        Format::Arrow => write_arrow(out, options, stations),
+        Format::Parquet => write_parquet(out, options, stations),
+    }
+}
+
+/// The whole report in one buffer, so it goes out in a single write instead of one per line.
+pub fn render(options: &Options, stations: &[(&Key, &WeatherData)]) -> Vec<u8> {
+    let mut report = Vec::with_capacity((stations.len() + 2) * STATION_REPORT_SIZE);
+    write_report(&mut report, options, stations).expect("writing to a Vec cannot fail");
+    report
+}
+
+/// Writes the report to `path` through a temporary file in the same directory, so readers see
+/// either the previous report or the complete new one.
+pub fn write_report_file(
+    path: &Path,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let partial = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let result = File::create(&partial).and_then(|mut file| {
+        file.write_all(&render(options, stations))?;
+        file.sync_all()?;
+        fs::rename(&partial, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn write_text(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    for (index, (key, data)) in stations.iter().enumerate() {
+        if let Some(source) = source_of(options, index) {
+            out.write_all(b"Source: ")?;
+            write_quoted(out, source)?;
+            out.write_all(b", ")?;
+        }
+        out.write_all(b"Station: ")?;
+        write_quoted(out, &station_name(key))?;
+        write!(
+            out,
+            ", Min: {}, Mean: {}, Max: {}",
+            data.min(),
+            data.mean(),
+            data.max()
+        )?;
+        if options.with_count {
+            write!(out, ", Count: {}", data.count)?;
+        }
+        for (percentile, value) in wanted(options).iter().zip(percentiles_of(options, key)) {
+            match value {
+                Some(value) => write!(out, ", {}: {}", percentile, value)?,
+                None => write!(out, ", {}: -", percentile)?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// `name` quoted the way `{:?}` quotes it. Plain ASCII names, nearly all of a report, are
+/// copied as they are instead of going through `Debug` char by char.
+fn write_quoted(out: &mut impl Write, name: &str) -> io::Result<()> {
+    let plain = name
+        .bytes()
+        .all(|b| (b.is_ascii_graphic() || b == b' ') && b != b'"' && b != b'\\');
+    if !plain {
+        return write!(out, "{:?}", name);
+    }
+    out.write_all(b"\"")?;
+    out.write_all(name.as_bytes())?;
+    out.write_all(b"\"")
+}
+
+fn write_challenge(
+    out: &mut impl Write,
+    with_count: bool,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    write!(out, "{{")?;
+    for (index, (key, data)) in stations.iter().enumerate() {
+        let separator = if index == 0 { "" } else { ", " };
+        write!(
+            out,
+            "{}{}={:.1}/{:.1}/{:.1}",
+            separator,
+            station_name(key),
+            data.min(),
+            data.mean(),
+            data.max()
+        )?;
+        if with_count {
+            write!(out, "/{}", data.count)?;
+        }
+    }
+    writeln!(out, "}}")
+}
+
+fn write_table(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    let with_count = options.with_count;
+    let names = stations
+        .iter()
+        .map(|(key, _)| station_name(key))
+        .collect::<Vec<_>>();
+    // Padding counts chars, which lines up most names; wide and combining ones may be off.
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Station".len());
+    let source_width = options.sources.map(|sources| {
+        sources
+            .iter()
+            .map(|source| source.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Source".len())
+    });
+    let count_width = stations
+        .iter()
+        .map(|(_, data)| data.count.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("Count".len());
+    if let Some(width) = source_width {
+        write!(out, "{:<width$}  ", "Source")?;
+    }
+    write!(
+        out,
+        "{:<width$}  {:>5}  {:>5}  {:>5}",
+        "Station",
+        "Min",
+        "Mean",
+        "Max",
+        width = width
+    )?;
+    if with_count {
+        write!(out, "  {:>width$}", "Count", width = count_width)?;
+    }
+    for percentile in wanted(options) {
+        write!(out, "  {:>5}", percentile.to_string())?;
+    }
+    writeln!(out)?;
+    for (index, (name, (key, data))) in names.iter().zip(stations).enumerate() {
+        if let (Some(width), Some(source)) = (source_width, source_of(options, index)) {
+            write!(out, "{:<width$}  ", source)?;
+        }
+        // `Tenths` does not pad, so the cells are formatted first.
+        write!(
+            out,
+            "{:<width$}  {:>5}  {:>5}  {:>5}",
+            name,
+            format!("{:.1}", data.min()),
+            format!("{:.1}", data.mean()),
+            format!("{:.1}", data.max()),
+            width = width
+        )?;
+        if with_count {
+            write!(out, "  {:>width$}", data.count, width = count_width)?;
+        }
+        for (percentile, value) in wanted(options).iter().zip(percentiles_of(options, key)) {
+            let width = percentile.to_string().len().max(5);
+            match value {
+                Some(value) => write!(out, "  {:>width$}", format!("{:.1}", value))?,
+                None => write!(out, "  {:>width$}", "-")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_markdown(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    let mut header = Vec::new();
+    if options.sources.is_some() {
+        header.push("Source".to_string());
+    }
+    header.extend([
+        "Station".to_string(),
+        "Min".into(),
+        "Mean".into(),
+        "Max".into(),
+    ]);
+    if options.with_count {
+        header.push("Count".into());
+    }
+    header.extend(wanted(options).iter().map(Percentile::to_string));
+    writeln!(out, "| {} |", header.join(" | "))?;
+    let names = 1 + options.sources.is_some() as usize;
+    writeln!(
+        out,
+        "|{}{}",
+        " :--- |".repeat(names),
+        " ---: |".repeat(header.len() - names)
+    )?;
+    for (index, (key, data)) in stations.iter().enumerate() {
+        if let Some(source) = source_of(options, index) {
+            write!(out, "| {} ", escape_markdown(source))?;
+        }
+        write!(
+            out,
+            "| {} | {:.1} | {:.1} | {:.1} |",
+            escape_markdown(&station_name(key)),
+            data.min(),
+            data.mean(),
+            data.max()
+        )?;
+        if options.with_count {
+            write!(out, " {} |", data.count)?;
+        }
+        for value in percentiles_of(options, key) {
+            match value {
+                Some(value) => write!(out, " {:.1} |", value)?,
+                None => write!(out, " - |")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Escapes a value for a Markdown table cell, which a `|` would end and a newline would break.
+fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '|' => escaped.push_str("\\|"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' | '\r' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_pg_copy(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    let percentile_columns = wanted(options)
+        .iter()
+        .map(|percentile| format!(", {}", percentile.column()))
+        .collect::<String>();
+    let source_column = if options.sources.is_some() {
+        "source, "
+    } else {
+        ""
+    };
+    writeln!(
+        out,
+        "COPY {} ({}station, min, mean, max, count{}) FROM STDIN;",
+        options.pg_table, source_column, percentile_columns
+    )?;
+    for (index, (key, data)) in stations.iter().enumerate() {
+        if let Some(source) = source_of(options, index) {
+            write!(out, "{}\t", escape_pg_copy(source))?;
+        }
+        write!(
+            out,
+            "{}\t{:.1}\t{:.1}\t{:.1}\t{}",
+            escape_pg_copy(&station_name(key)),
+            data.min(),
+            data.mean(),
+            data.max(),
+            data.count
+        )?;
+        for value in percentiles_of(options, key) {
+            match value {
+                Some(value) => write!(out, "\t{:.1}", value)?,
+                None => write!(out, "\t\\N")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "\\.")
+}
+
+/// Escapes a value for the `COPY` text format, where backslash, tab, newline and carriage
+/// return are special.
+fn escape_pg_copy(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_json(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (index, (key, data)) in stations.iter().enumerate() {
+        let separator = if index + 1 < stations.len() { "," } else { "" };
+        write!(out, "  {{")?;
+        if let Some(source) = source_of(options, index) {
+            write!(out, "\"source\": \"{}\", ", escape_json(source))?;
+        }
+        write!(
+            out,
+            "\"station\": \"{}\", \"min\": {:.1}, \"mean\": {:.1}, \"max\": {:.1}, \"count\": {}",
+            escape_json(&station_name(key)),
+            data.min(),
+            data.mean(),
+            data.max(),
+            data.count
+        )?;
+        for (percentile, value) in wanted(options).iter().zip(percentiles_of(options, key)) {
+            match value {
+                Some(value) => write!(out, ", \"{}\": {:.1}", percentile.column(), value)?,
+                None => write!(out, ", \"{}\": null", percentile.column())?,
+            }
+        }
+        writeln!(out, "}}{}", separator)?;
+    }
+    writeln!(out, "]")
+}
+
+/// Escapes a value for a JSON string, where quotes, backslashes and control characters must be
+/// escaped.
+pub(crate) fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The name of `key`, borrowed unless it is not valid UTF-8.
+fn station_name(key: &Key) -> Cow<'_, str> {
+    String::from_utf8_lossy(key.as_bytes())
+}
+
+#[cfg(feature = "arrow")]
+fn write_arrow(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    let batch = record_batch(options, stations);
+    let mut writer =
+        arrow_ipc::writer::FileWriter::try_new(out, &batch.schema()).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "arrow"))]
+fn write_arrow(_: &mut impl Write, _: &Options, _: &[(&Key, &WeatherData)]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--format arrow needs a build with `--features arrow`",
+    ))
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(
+    out: &mut impl Write,
+    options: &Options,
+    stations: &[(&Key, &WeatherData)],
+) -> io::Result<()> {
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::{self, GzipLevel, ZstdLevel};
+    use parquet::file::properties::WriterProperties;
+
+    let compression = match options.compression {
+        Compression::None => basic::Compression::UNCOMPRESSED,
+        Compression::Snappy => basic::Compression::SNAPPY,
+        Compression::Gzip => basic::Compression::GZIP(GzipLevel::default()),
+        Compression::Lz4 => basic::Compression::LZ4_RAW,
+        Compression::Zstd => basic::Compression::ZSTD(ZstdLevel::default()),
+    };
+    let properties = WriterProperties::builder()
+        .set_compression(compression)
+        .build();
+    let batch = record_batch(options, stations);
+    // The writer wants to own a `Send` sink, which `out` need not be.
+    let mut file = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut file, batch.schema(), Some(properties))
+        .map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+    out.write_all(&file)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_: &mut impl Write, _: &Options, _: &[(&Key, &WeatherData)]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--format parquet needs a build with `--features parquet`",
+    ))
+}
+
+/// The report as one Arrow record batch, in report order. The schema records the version that
+/// wrote it, and the unit of the temperatures, which are in degrees. Percentiles are nullable,
+/// for a station without a digest.
+#[cfg(any(feature = "arrow", feature = "parquet"))]
+fn record_batch(options: &Options, stations: &[(&Key, &WeatherData)]) -> arrow_array::RecordBatch {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+
+    let degrees = |temperature: fn(&WeatherData) -> crate::Tenths| -> ArrayRef {
+        Arc::new(Float64Array::from_iter_values(
+            stations.iter().map(|(_, data)| temperature(data).degrees()),
+        ))
+    };
+    let mut columns: Vec<ArrayRef> = Vec::new();
+    if let Some(sources) = options.sources {
+        columns.push(Arc::new(StringArray::from_iter_values(sources)));
+    }
+    columns.extend([
+        Arc::new(StringArray::from_iter_values(
+            stations.iter().map(|(key, _)| station_name(key)),
+        )) as ArrayRef,
+        degrees(WeatherData::min),
+        degrees(WeatherData::mean),
+        degrees(WeatherData::max),
+        Arc::new(UInt64Array::from_iter_values(
+            stations.iter().map(|(_, data)| data.count as u64),
+        )),
+    ]);
+    let percentiles = stations
+        .iter()
+        .map(|(key, _)| percentiles_of(options, key))
+        .collect::<Vec<_>>();
+    for column in 0..wanted(options).len() {
+        columns.push(Arc::new(Float64Array::from_iter(
+            percentiles
+                .iter()
+                .map(|values| values[column].map(Tenths::degrees)),
+        )));
+    }
+
+    let temperature = |name: &str, nullable| {
+        Field::new(name, DataType::Float64, nullable)
+            .with_metadata(HashMap::from([("unit".to_string(), "°C".to_string())]))
+    };
+    let mut fields = Vec::new();
+    if options.sources.is_some() {
+        fields.push(Field::new("source", DataType::Utf8, false));
+    }
+    fields.extend([
+        Field::new("station", DataType::Utf8, false),
+        temperature("min", false),
+        temperature("mean", false),
+        temperature("max", false),
+        Field::new("count", DataType::UInt64, false),
+    ]);
+    fields.extend(
+        wanted(options)
+            .iter()
+            .map(|percentile| temperature(&percentile.column(), true)),
+    );
+    let schema = Schema::new(fields).with_metadata(HashMap::from([(
+        "onebrc.version".to_string(),
+        env!("CARGO_PKG_VERSION").to_string(),
+    )]));
+    RecordBatch::try_new(Arc::new(schema), columns).expect("the columns match the schema")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aggregate_files, aggregate_sources, finish, process_buffer, station_name, Config,
+        MIN_CHUNK_SIZE,
+    };
+
+    #[test]
+    fn text_report_quotes_names_like_debug() {
+        let names = [
+            "Hamburg",
+            "St. John's",
+            "a\"b",
+            "c\\d",
+            "tab\there",
+            "Zürich",
+        ];
+        let (mut stations, _) = process_buffer(
+            names
+                .iter()
+                .map(|name| format!("{};1.0\n", name))
+                .collect::<String>()
+                .as_bytes(),
+        );
+        let sorted = finish(&mut stations);
+        let options = Options {
+            format: Format::Text,
+            pg_table: "",
+            compression: Compression::Zstd,
+            with_count: false,
+            percentiles: None,
+            sources: None,
+        };
+        let report = String::from_utf8(render(&options, &sorted)).unwrap();
+        for ((key, _), line) in sorted.iter().zip(report.lines()) {
+            let expected = format!("Station: {:?}, Min: 1, Mean: 1, Max: 1", station_name(key));
+            assert_eq!(line, expected);
+        }
+        assert_eq!(report.lines().count(), names.len());
+    }
+
+    #[test]
+    fn pg_copy_escapes_what_copy_treats_specially() {
+        let input = "back\\slash;1.0\ntab\there;2.0\n\\.;3.0\ncarriage\rreturn;4.0\nplain;5.0\n";
+        let (mut stations, _) = process_buffer(input.as_bytes());
+        let sorted = finish(&mut stations);
+        let options = Options {
+            format: Format::PgCopy,
+            pg_table: "weather",
+            compression: Compression::Zstd,
+            with_count: false,
+            percentiles: None,
+            sources: None,
+        };
+        let report = String::from_utf8(render(&options, &sorted)).unwrap();
+        assert_eq!(
+            report,
+            concat!(
+                "COPY weather (station, min, mean, max, count) FROM STDIN;\n",
+                // A name of `\.` must not end the data early.
+                "\\\\.\t3.0\t3.0\t3.0\t1\n",
+                "back\\\\slash\t1.0\t1.0\t1.0\t1\n",
+                "carriage\\rreturn\t4.0\t4.0\t4.0\t1\n",
+                "plain\t5.0\t5.0\t5.0\t1\n",
+                "tab\\there\t2.0\t2.0\t2.0\t1\n",
+                "\\.\n",
+            )
+        );
+    }
+
+    #[test]
+    fn report_sorts_by_decoded_name_or_by_value() {
+        // Cut to 100 bytes, the overlong name would end in half an `é`.
+        let long = format!("a{}", "é".repeat(60));
+        let input = format!("B;5.0\nA;5.0\nC;-1.0\n{};9.0\nB;5.0\n", long);
+        let (mut stations, _) = process_buffer(input.as_bytes());
+        let mut sorted = finish(&mut stations);
+        let names = |sorted: &[(&Key, &WeatherData)]| {
+            sorted
+                .iter()
+                .map(|(key, _)| station_name(key))
+                .collect::<Vec<_>>()
+        };
+        let cut = format!("a{}", "é".repeat(49));
+        assert_eq!(names(&sorted), ["A", "B", "C", cut.as_str()]);
+
+        sort(&mut sorted, SortBy::Mean, true);
+        assert_eq!(names(&sorted), [cut.as_str(), "A", "B", "C"]);
+        sort(&mut sorted, SortBy::Count, false);
+        assert_eq!(names(&sorted)[3], "B");
+
+        sort(&mut sorted, SortBy::Mean, false);
+        let mut extremes = sorted.clone();
+        keep_extremes(&mut extremes, false, Some(1), Some(1));
+        assert_eq!(names(&extremes), ["C", cut.as_str()]);
+        sort(&mut sorted, SortBy::Mean, true);
+        keep_extremes(&mut sorted, true, Some(2), None);
+        assert_eq!(names(&sorted), [cut.as_str(), "A"]);
+        keep_extremes(&mut sorted, true, Some(5), Some(5));
+        assert_eq!(sorted.len(), 2);
+    }
+
+    #[test]
+    fn summary_names_the_first_station_at_each_extreme() {
+        let (mut stations, _) =
+            process_buffer(b"Oslo;-3.5\nLima;20.1\nOslo;1.0\nAbha;-3.5\nLima;-0.2\n");
+        let sorted = finish(&mut stations);
+        assert_eq!(
+            Summary::of(&sorted),
+            Summary {
+                rows: 5,
+                stations: 3,
+                coldest: Some(("Abha".to_string(), Tenths(-35))),
+                hottest: Some(("Lima".to_string(), Tenths(201))),
+                mean: Some(Tenths(28)),
+            }
+        );
+        assert_eq!(Summary::of(&[]).mean, None);
+    }
+
+    #[test]
+    fn table_and_markdown_reports_line_up() {
+        let (mut stations, _) = process_buffer("Zürich;-3.5\na|b;12.0\nZürich;1.0\n".as_bytes());
+        let sorted = finish(&mut stations);
+        let render = |format| {
+            let options = Options {
+                format,
+                pg_table: "",
+                compression: Compression::Zstd,
+                with_count: false,
+                percentiles: None,
+                sources: None,
+            };
+            String::from_utf8(render(&options, &sorted)).unwrap()
+        };
+        assert_eq!(
+            render(Format::Table),
+            "Station    Min   Mean    Max\n\
+             Zürich    -3.5   -1.2    1.0\n\
+             a|b       12.0   12.0   12.0\n"
+        );
+        assert_eq!(
+            render(Format::Md),
+            "| Station | Min | Mean | Max |\n\
+             | :--- | ---: | ---: | ---: |\n\
+             | Zürich | -3.5 | -1.2 | 1.0 |\n\
+             | a\\|b | 12.0 | 12.0 | 12.0 |\n"
+        );
+    }
+
+    #[test]
+    fn with_count_adds_the_count_to_every_format() {
+        let (mut stations, _) = process_buffer(b"Oslo;-3.5\nLima;20.1\nOslo;1.0\n");
+        let sorted = finish(&mut stations);
+        let render = |format| {
+            let options = Options {
+                format,
+                pg_table: "",
+                compression: Compression::Zstd,
+                with_count: true,
+                percentiles: None,
+                sources: None,
+            };
+            String::from_utf8(render(&options, &sorted)).unwrap()
+        };
+        assert_eq!(
+            render(Format::Text),
+            "Station: \"Lima\", Min: 20.1, Mean: 20.1, Max: 20.1, Count: 1\n\
+             Station: \"Oslo\", Min: -3.5, Mean: -1.2, Max: 1, Count: 2\n"
+        );
+        assert_eq!(
+            render(Format::Challenge),
+            "{Lima=20.1/20.1/20.1/1, Oslo=-3.5/-1.2/1.0/2}\n"
+        );
+        assert_eq!(
+            render(Format::Table),
+            "Station    Min   Mean    Max  Count\n\
+             Lima      20.1   20.1   20.1      1\n\
+             Oslo      -3.5   -1.2    1.0      2\n"
+        );
+        assert!(render(Format::Md).ends_with("| Oslo | -3.5 | -1.2 | 1.0 | 2 |\n"));
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn arrow_report_reads_back() {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::{Float64Type, UInt64Type};
+
+        let (mut stations, _) = process_buffer(b"Oslo;-3.5\nZ\xc3\xbcrich;12.0\nOslo;1.0\n");
+        let sorted = finish(&mut stations);
+        let options = Options {
+            format: Format::Arrow,
+            pg_table: "",
+            compression: Compression::Zstd,
+            with_count: false,
+            percentiles: None,
+            sources: None,
+        };
+        let report = render(&options, &sorted);
+        let mut reader =
+            arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(report), None).unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        assert!(reader.next().is_none());
+
+        let names = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["station", "min", "mean", "max", "count"]);
+        let stations = batch.column(0).as_string::<i32>();
+        assert_eq!(
+            stations.iter().collect::<Vec<_>>(),
+            [Some("Oslo"), Some("Zürich")]
+        );
+        let column = |index: usize| {
+            batch
+                .column(index)
+                .as_primitive::<Float64Type>()
+                .values()
+                .to_vec()
+        };
+        assert_eq!(column(1), [-3.5, 12.0]);
+        assert_eq!(column(2), [-1.2, 12.0]);
+        assert_eq!(column(3), [1.0, 12.0]);
+        let counts = batch
+            .column(4)
+            .as_primitive::<UInt64Type>()
+            .values()
+            .to_vec();
+        assert_eq!(counts, [2, 1]);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_report_keeps_its_schema_and_codec() {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::Float64Type;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use parquet::basic::Compression as Codec;
+
+        let (mut stations, _) = process_buffer(b"Oslo;-3.5\nLima;20.1\nOslo;1.0\n");
+        let sorted = finish(&mut stations);
+        let options = Options {
+            format: Format::Parquet,
+            pg_table: "",
+            compression: Compression::Snappy,
+            with_count: false,
+            percentiles: None,
+            sources: None,
+        };
+        let path = std::env::temp_dir().join(format!("onebrc-report-test-{}", std::process::id()));
+        write_report_file(&path, &options, &sorted).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        let column = builder.metadata().row_group(0).column(1);
+        assert_eq!(column.compression(), Codec::SNAPPY);
+        let schema = builder.schema().clone();
+        assert_eq!(
+            schema.metadata()["onebrc.version"],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(
+            schema.field_with_name("mean").unwrap().metadata()["unit"],
+            "°C"
+        );
+
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+        let means = batch
+            .column(2)
+            .as_primitive::<Float64Type>()
+            .values()
+            .to_vec();
+        assert_eq!(means, [20.1, -1.2]);
+    }
+
+    #[test]
+    fn sources_keep_their_stations_apart() {
+        let dir = std::env::temp_dir().join(format!("onebrc-sources-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (eu, us) = (dir.join("eu.txt"), dir.join("us.txt"));
+        std::fs::write(&eu, "Hamburg;12.0\nOslo;-3.0\nHamburg;-2.0\n").unwrap();
+        let mut us_rows = "Hamburg;1.5\nLima;20.5\n".repeat(5000);
+        us_rows.push_str("Hamburg;30.0\n");
+        std::fs::write(&us, &us_rows).unwrap();
+        let inputs = [eu.clone(), us.clone()];
+        let config = Config {
+            chunk_size: MIN_CHUNK_SIZE,
+            ..Config::default()
+        };
+        let (mut total, mut sources) = aggregate_sources(&inputs, &config).unwrap();
+        let mut merged = aggregate_files(&inputs, &config).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(total.total_lines, 10_004);
+        let text = |stations: &[(&Key, &WeatherData)]| {
+            let options = Options {
+                format: Format::Text,
+                pg_table: "",
+                compression: Compression::Zstd,
+                with_count: true,
+                percentiles: None,
+                sources: None,
+            };
+            String::from_utf8(render(&options, stations)).unwrap()
+        };
+        assert_eq!(
+            text(&finish(&mut total.station_temperatures)),
+            text(&finish(&mut merged.station_temperatures))
+        );
+
+        let [eu_stations, us_stations] = &mut sources[..] else {
+            panic!("{} sources", sources.len());
+        };
+        let mut stations = finish(eu_stations);
+        let eu_count = stations.len();
+        stations.extend(finish(us_stations));
+        let labels = ["eu".to_string(), "eu".into(), "us".into(), "us".into()];
+        let options = Options {
+            format: Format::Json,
+            pg_table: "",
+            compression: Compression::Zstd,
+            with_count: false,
+            percentiles: None,
+            sources: Some(&labels),
+        };
+        assert_eq!(eu_count, 2);
+        assert_eq!(
+            String::from_utf8(render(&options, &stations)).unwrap(),
+            concat!(
+                "[\n",
+                "  {\"source\": \"eu\", \"station\": \"Hamburg\", \"min\": -2.0, \"mean\": 5.0, \"max\": 12.0, \"count\": 2},\n",
+                "  {\"source\": \"eu\", \"station\": \"Oslo\", \"min\": -3.0, \"mean\": -3.0, \"max\": -3.0, \"count\": 1},\n",
+                "  {\"source\": \"us\", \"station\": \"Hamburg\", \"min\": 1.5, \"mean\": 1.5, \"max\": 30.0, \"count\": 5001},\n",
+                "  {\"source\": \"us\", \"station\": \"Lima\", \"min\": 20.5, \"mean\": 20.5, \"max\": 20.5, \"count\": 5000}\n",
+                "]\n",
+            )
+        );
+    }
+}
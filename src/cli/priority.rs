@@ -0,0 +1,41 @@
+use std::io;
+
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadPriority {
+    /// Yield to interactive work (nice 10)
+    Low,
+    Normal,
+    /// Take precedence over other work (nice -10, usually needs privileges)
+    High,
+}
+
+impl ThreadPriority {
+    pub fn nice(self) -> i32 {
+        match self {
+            ThreadPriority::Low => 10,
+            ThreadPriority::Normal => 0,
+            ThreadPriority::High => -10,
+        }
+    }
+}
+
+/// Sets the niceness of the calling thread before it spawns the workers. Linux keeps it per
+/// thread and new threads inherit it; other unix systems apply it to the whole process.
+#[cfg(unix)]
+pub fn set_nice(nice: i32) -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn set_nice(_nice: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread priorities are only supported on unix",
+    ))
+}
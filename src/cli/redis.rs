@@ -0,0 +1,407 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::output::escape_json;
+use crate::{station_name, Key, StationTemperatures, Tenths};
+
+/// How long connecting, and every write and reply after that, may take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A `redis://[[user]:password@]host[:port][/db]` URL.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Url {
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub database: u32,
+}
+
+impl FromStr for Url {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Url, String> {
+        let rest = value
+            .strip_prefix("redis://")
+            .ok_or_else(|| format!("`{}` is not a redis:// URL", value))?;
+        let (authority, database) = rest.split_once('/').unwrap_or((rest, ""));
+        let database = match database {
+            "" => 0,
+            database => database
+                .parse()
+                .map_err(|_| format!("`{}` is not a database number", database))?,
+        };
+        let (credentials, host) = match authority.rsplit_once('@') {
+            Some((credentials, host)) => (Some(credentials), host),
+            None => (None, authority),
+        };
+        let (username, password) = match credentials.map(|credentials| credentials.split_once(':'))
+        {
+            None => (None, None),
+            Some(None) => return Err(format!("`{}` has credentials without a password", value)),
+            Some(Some(("", password))) => (None, Some(password.to_string())),
+            Some(Some((username, password))) => {
+                (Some(username.to_string()), Some(password.to_string()))
+            }
+        };
+        let address = match host {
+            "" => return Err(format!("`{}` has no host", value)),
+            host if host
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port.parse::<u16>().is_ok()) =>
+            {
+                host.to_string()
+            }
+            host => format!("{}:6379", host),
+        };
+        Ok(Url {
+            address,
+            username,
+            password,
+            database,
+        })
+    }
+}
+
+// Keeps the password out of debug output.
+impl fmt::Debug for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "redis://{}/{}", self.address, self.database)
+    }
+}
+
+/// Publishes station totals to Redis, one hash per station under `<prefix><station>` with the
+/// fields `min`, `mean`, `max`, `count` and `total` in degrees, and optionally announces every
+/// update on a pub/sub channel.
+///
+/// The commands of an update go out as one pipeline. The connection is made on the first
+/// update, and again on the next one after any error; that update then rewrites every hash,
+/// so Redis catches up with whatever it missed while it was down.
+pub struct Publisher {
+    url: Url,
+    prefix: String,
+    channel: Option<String>,
+    connection: Option<BufReader<TcpStream>>,
+    behind: bool,
+    removed: Vec<Key>,
+}
+
+impl Publisher {
+    pub fn new(url: Url, prefix: String, channel: Option<String>) -> Publisher {
+        Publisher {
+            url,
+            prefix,
+            channel,
+            connection: None,
+            behind: true,
+            removed: Vec::new(),
+        }
+    }
+
+    /// Rewrites the hashes of the `changed` stations of `snapshot` and deletes those of
+    /// `removed`, then publishes a `{"lines":N,"stations":[...],"removed":[...]}` message
+    /// naming them on the channel, `lines` being how many the update added.
+    pub fn publish<'a>(
+        &mut self,
+        snapshot: &StationTemperatures,
+        changed: impl IntoIterator<Item = &'a Key>,
+        removed: &[Key],
+        lines: u64,
+    ) -> io::Result<()> {
+        self.removed.extend(removed);
+        self.removed.retain(|key| !snapshot.contains_key(key));
+        let changed = match self.behind {
+            true => snapshot.keys().collect(),
+            false => changed.into_iter().collect(),
+        };
+        let result = self.send(snapshot, changed, lines);
+        match result {
+            Ok(()) => {
+                self.behind = false;
+                self.removed.clear();
+            }
+            Err(_) => {
+                self.behind = true;
+                self.connection = None;
+            }
+        }
+        result
+    }
+
+    fn send(
+        &mut self,
+        snapshot: &StationTemperatures,
+        changed: Vec<&Key>,
+        lines: u64,
+    ) -> io::Result<()> {
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+        }
+        let connection = self.connection.as_mut().unwrap();
+
+        let mut out = BufWriter::new(connection.get_ref());
+        let mut commands = 0;
+        for key in &self.removed {
+            command(&mut out, &[b"DEL", &hash(&self.prefix, key)])?;
+            commands += 1;
+        }
+        for key in &changed {
+            let mut data = snapshot[*key];
+            data.update_mean();
+            let figures = [
+                data.min().to_string(),
+                data.mean().to_string(),
+                data.max().to_string(),
+                data.count.to_string(),
+                Tenths(data.total_temperature).to_string(),
+            ];
+            command(
+                &mut out,
+                &[
+                    b"HSET",
+                    &hash(&self.prefix, key),
+                    b"min",
+                    figures[0].as_bytes(),
+                    b"mean",
+                    figures[1].as_bytes(),
+                    b"max",
+                    figures[2].as_bytes(),
+                    b"count",
+                    figures[3].as_bytes(),
+                    b"total",
+                    figures[4].as_bytes(),
+                ],
+            )?;
+            commands += 1;
+        }
+        if let Some(channel) = &self.channel {
+            let names = |keys: &mut dyn Iterator<Item = &Key>| {
+                keys.map(|key| format!("\"{}\"", escape_json(&station_name(key))))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            let message = format!(
+                "{{\"lines\":{},\"stations\":[{}],\"removed\":[{}]}}",
+                lines,
+                names(&mut changed.iter().copied()),
+                names(&mut self.removed.iter())
+            );
+            command(
+                &mut out,
+                &[b"PUBLISH", channel.as_bytes(), message.as_bytes()],
+            )?;
+            commands += 1;
+        }
+        out.flush()?;
+        drop(out);
+
+        for _ in 0..commands {
+            reply(connection)?;
+        }
+        Ok(())
+    }
+
+    fn connect(&self) -> io::Result<BufReader<TcpStream>> {
+        let address = self.url.address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no address", self.url.address),
+            )
+        })?;
+        let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut connection = BufReader::new(stream);
+
+        let mut setup: Vec<Vec<&[u8]>> = Vec::new();
+        match (&self.url.username, &self.url.password) {
+            (Some(username), Some(password)) => {
+                setup.push(vec![b"AUTH", username.as_bytes(), password.as_bytes()])
+            }
+            (None, Some(password)) => setup.push(vec![b"AUTH", password.as_bytes()]),
+            _ => {}
+        }
+        let database = self.url.database.to_string();
+        if self.url.database != 0 {
+            setup.push(vec![b"SELECT", database.as_bytes()]);
+        }
+        for arguments in &setup {
+            command(&mut connection.get_ref(), arguments)?;
+            reply(&mut connection)?;
+        }
+        Ok(connection)
+    }
+}
+
+fn hash(prefix: &str, key: &Key) -> Vec<u8> {
+    [prefix.as_bytes(), key.as_bytes()].concat()
+}
+
+/// Writes a command as an array of bulk strings, which carry station names as they are,
+/// whatever bytes they hold.
+fn command(out: &mut impl Write, arguments: &[&[u8]]) -> io::Result<()> {
+    write!(out, "*{}\r\n", arguments.len())?;
+    for argument in arguments {
+        write!(out, "${}\r\n", argument.len())?;
+        out.write_all(argument)?;
+        out.write_all(b"\r\n")?;
+    }
+    Ok(())
+}
+
+/// Reads one reply, failing on an error reply. Only the simple replies the commands above
+/// get back are understood: status, error and integer.
+fn reply(connection: &mut impl BufRead) -> io::Result<()> {
+    let mut line = String::new();
+    if connection.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Redis closed the connection",
+        ));
+    }
+    match line.as_bytes().first() {
+        Some(b'+' | b':') => Ok(()),
+        Some(b'-') => Err(io::Error::other(format!(
+            "Redis replied {}",
+            line[1..].trim_end()
+        ))),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected Redis reply {:?}", line.trim_end()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_buffer;
+
+    #[test]
+    fn redis_urls_take_credentials_port_and_database() {
+        let url: Url = "redis://:secret@cache.internal/2".parse().unwrap();
+        assert_eq!(url.address, "cache.internal:6379");
+        assert_eq!(
+            (url.username, url.password, url.database),
+            (None, Some("secret".to_string()), 2)
+        );
+        let url: Url = "redis://dash:pw@127.0.0.1:7000".parse().unwrap();
+        assert_eq!(url.address, "127.0.0.1:7000");
+        assert_eq!(url.username.as_deref(), Some("dash"));
+        assert!("http://localhost".parse::<Url>().is_err());
+        assert!("redis://localhost/db".parse::<Url>().is_err());
+        assert!("redis://secret@localhost".parse::<Url>().is_err());
+    }
+
+    /// Accepts one connection and answers every command with `reply`, sending the commands on
+    /// as they arrive.
+    fn fake_redis(
+        reply: &'static str,
+    ) -> (std::net::SocketAddr, std::sync::mpsc::Receiver<Vec<String>>) {
+        use std::io::{BufRead, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, commands) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            let mut read_line = |reader: &mut std::io::BufReader<_>| {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                line.trim_end()[1..].parse::<usize>().ok()
+            };
+            while let Some(arguments) = read_line(&mut reader) {
+                let command = (0..arguments)
+                    .map(|_| {
+                        let mut argument = vec![0; read_line(&mut reader).unwrap() + 2];
+                        reader.read_exact(&mut argument).unwrap();
+                        String::from_utf8_lossy(&argument[..argument.len() - 2]).into_owned()
+                    })
+                    .collect();
+                (&stream).write_all(reply.as_bytes()).unwrap();
+                if sender.send(command).is_err() {
+                    break;
+                }
+            }
+        });
+        (address, commands)
+    }
+
+    #[test]
+    fn redis_publishes_changed_stations_and_removals() {
+        let mut snapshot = process_buffer(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n").0;
+        let (address, commands) = fake_redis(":1\r\n");
+        let url = format!("redis://:pw@{}/3", address).parse().unwrap();
+        let mut publisher = Publisher::new(url, "t:".to_string(), Some("updates".to_string()));
+
+        // The first update publishes every station, whatever changed.
+        publisher.publish(&snapshot, [], &[], 0).unwrap();
+        let mut received = commands.iter().take(5).collect::<Vec<_>>();
+        assert_eq!(received[..2], [vec!["AUTH", "pw"], vec!["SELECT", "3"]]);
+        assert_eq!(received[4][..2], ["PUBLISH", "updates"]);
+        received[2..4].sort();
+        assert_eq!(
+            received[2],
+            [
+                "HSET",
+                "t:Bulawayo",
+                "min",
+                "8.9",
+                "mean",
+                "8.9",
+                "max",
+                "8.9",
+                "count",
+                "1",
+                "total",
+                "8.9"
+            ]
+        );
+        assert_eq!(
+            received[3],
+            [
+                "HSET",
+                "t:Hamburg",
+                "min",
+                "-3.4",
+                "mean",
+                "4.3",
+                "max",
+                "12",
+                "count",
+                "2",
+                "total",
+                "8.6"
+            ]
+        );
+
+        let bulawayo = Key::new(b"Bulawayo");
+        snapshot.remove(&bulawayo);
+        let hamburg = Key::new(b"Hamburg");
+        publisher
+            .publish(&snapshot, [&hamburg], &[bulawayo], 7)
+            .unwrap();
+        let received = commands.iter().take(3).collect::<Vec<_>>();
+        assert_eq!(received[0], ["DEL", "t:Bulawayo"]);
+        assert_eq!(received[1][1], "t:Hamburg");
+        assert_eq!(
+            received[2][2],
+            r#"{"lines":7,"stations":["Hamburg"],"removed":["Bulawayo"]}"#
+        );
+    }
+
+    #[test]
+    fn redis_errors_fail_the_update() {
+        let snapshot = process_buffer(b"Hamburg;12.0\n").0;
+        let (address, _commands) = fake_redis("-WRONGPASS invalid password\r\n");
+        let url = format!("redis://:pw@{}", address).parse().unwrap();
+        let mut publisher = Publisher::new(url, "t:".to_string(), None);
+
+        let err = publisher.publish(&snapshot, [], &[], 0).unwrap_err();
+        assert_eq!(err.to_string(), "Redis replied WRONGPASS invalid password");
+    }
+}
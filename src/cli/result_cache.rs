@@ -0,0 +1,258 @@
+/*
+* A cached result is one file per input and projection:
+    magic "OBRR", version u32
+    input: length u64, modified seconds u64 and nanoseconds u32, sample checksum u64
+    total lines u64, station count u32
+    per station: key [u8; 100], total i64, min i32 and max i32 temperature tenths, count u32
+* All integers are little endian. The temperatures are the merged ones, before `finish`.
+*/
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use ahash::AHashMap;
+
+use crate::projection::Projection;
+use crate::{Key, StationTemperatures, WeatherData, KEY_SIZE};
+
+const MAGIC: &[u8; 4] = b"OBRR";
+const VERSION: u32 = 4;
+const HEADER_SIZE: usize = 4 + 4 + 8 + 8 + 4 + 8 + 8 + 4;
+const ENTRY_SIZE: usize = KEY_SIZE + 8 + 4 * 3;
+/// Bytes checksummed at either end of the input.
+const SAMPLE_SIZE: u64 = 64 << 10;
+
+/// `$XDG_CACHE_HOME/onebrc`, falling back to `~/.cache/onebrc`.
+pub fn default_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|cache| cache.join("onebrc"))
+}
+
+/// What a cached result must match to be reused. Checksumming all of a large input would
+/// cost as much as the scan it saves, so only its ends are read; any write that goes through
+/// the file system also moves the modification time.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    path: PathBuf,
+    len: u64,
+    modified: (u64, u32),
+    sample: u64,
+}
+
+impl Fingerprint {
+    pub fn of(input: &Path) -> io::Result<Fingerprint> {
+        let mut file = File::open(input)?;
+        let metadata = file.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut hasher = DefaultHasher::new();
+        let mut sample = Vec::with_capacity(SAMPLE_SIZE as usize);
+        file.by_ref().take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+        file.seek(SeekFrom::Start(
+            metadata
+                .len()
+                .saturating_sub(SAMPLE_SIZE)
+                .max(sample.len() as u64),
+        ))?;
+        file.read_to_end(&mut sample)?;
+        sample.hash(&mut hasher);
+
+        Ok(Fingerprint {
+            path: fs::canonicalize(input)?,
+            len: metadata.len(),
+            modified: (modified.as_secs(), modified.subsec_nanos()),
+            sample: hasher.finish(),
+        })
+    }
+}
+
+/// Entry for `fingerprint` under `dir`. Results are only comparable between runs of the same
+/// build with the same projection, so both are part of the name.
+fn entry_path(dir: &Path, fingerprint: &Fingerprint, projection: Option<Projection>) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    fingerprint.path.hash(&mut hasher);
+    projection
+        .map(|projection| {
+            (
+                projection.station_column,
+                projection.value_column,
+                projection.format,
+            )
+        })
+        .hash(&mut hasher);
+    dir.join(format!("{:016x}.result", hasher.finish()))
+}
+
+/// The cached stations and line count, `None` when there is no entry or it is stale.
+pub fn lookup(
+    dir: &Path,
+    fingerprint: &Fingerprint,
+    projection: Option<Projection>,
+) -> io::Result<Option<(StationTemperatures, u64)>> {
+    let data = match fs::read(entry_path(dir, fingerprint, projection)) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if data.len() < HEADER_SIZE || &data[..4] != MAGIC || read_u32(&data[4..]) != VERSION {
+        return Ok(None);
+    }
+
+    let stored = Fingerprint {
+        path: fingerprint.path.clone(),
+        len: read_u64(&data[8..]),
+        modified: (read_u64(&data[16..]), read_u32(&data[24..])),
+        sample: read_u64(&data[28..]),
+    };
+    let total_lines = read_u64(&data[36..]);
+    let station_count = read_u32(&data[44..]) as usize;
+    if stored != *fingerprint || data.len() != HEADER_SIZE + station_count * ENTRY_SIZE {
+        return Ok(None);
+    }
+
+    let station_temperatures = data[HEADER_SIZE..]
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| {
+            let name = &entry[..KEY_SIZE];
+            let key = Key::new(&name[..name.iter().position(|&b| b == 0).unwrap_or(KEY_SIZE)]);
+            let field = |index: usize| &entry[KEY_SIZE + 8 + index * 4..];
+            let data = WeatherData {
+                total_temperature: read_u64(&entry[KEY_SIZE..]) as i64,
+                min_temperature: read_u32(field(0)) as i32,
+                max_temperature: read_u32(field(1)) as i32,
+                count: read_u32(field(2)),
+                mean_temperature: 0,
+            };
+            (key, data)
+        })
+        .collect::<AHashMap<_, _>>();
+    Ok(Some((station_temperatures, total_lines)))
+}
+
+/// Stores a complete result, replacing any stale entry for the same input.
+pub fn store(
+    dir: &Path,
+    fingerprint: &Fingerprint,
+    projection: Option<Projection>,
+    station_temperatures: &StationTemperatures,
+    total_lines: u64,
+) -> io::Result<()> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + station_temperatures.len() * ENTRY_SIZE);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&fingerprint.len.to_le_bytes());
+    data.extend_from_slice(&fingerprint.modified.0.to_le_bytes());
+    data.extend_from_slice(&fingerprint.modified.1.to_le_bytes());
+    data.extend_from_slice(&fingerprint.sample.to_le_bytes());
+    data.extend_from_slice(&total_lines.to_le_bytes());
+    data.extend_from_slice(&(station_temperatures.len() as u32).to_le_bytes());
+    for (key, station) in station_temperatures {
+        data.extend_from_slice(&key.bytes);
+        data.extend_from_slice(&station.total_temperature.to_le_bytes());
+        data.extend_from_slice(&station.min_temperature.to_le_bytes());
+        data.extend_from_slice(&station.max_temperature.to_le_bytes());
+        data.extend_from_slice(&station.count.to_le_bytes());
+    }
+
+    fs::create_dir_all(dir)?;
+    let path = entry_path(dir, fingerprint, projection);
+    // Write aside and rename, so a concurrent lookup never sees half an entry.
+    let partial = path.with_extension(format!("partial.{}", std::process::id()));
+    fs::write(&partial, &data)?;
+    fs::rename(&partial, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_buffer, projection};
+
+    #[test]
+    fn result_cache_round_trips_until_the_input_changes() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = std::env::temp_dir().join(format!("onebrc-cache-test-{}", std::process::id()));
+        let path = dir.with_extension("txt");
+        let input = "Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n";
+        std::fs::write(&path, input).unwrap();
+        let (station_temperatures, lines) = process_buffer(input.as_bytes());
+        let fingerprint = Fingerprint::of(&path).unwrap();
+        let csv = Some(projection::Projection {
+            station_column: 0,
+            value_column: 1,
+            format: projection::InputFormat::Csv,
+        });
+
+        assert!(lookup(&dir, &fingerprint, None).unwrap().is_none());
+        store(
+            &dir,
+            &fingerprint,
+            None,
+            &station_temperatures,
+            lines as u64,
+        )
+        .unwrap();
+        let (cached, cached_lines) = lookup(&dir, &fingerprint, None).unwrap().unwrap();
+        assert_eq!(cached_lines, 3);
+        assert_eq!(cached.len(), station_temperatures.len());
+        for (key, data) in &station_temperatures {
+            let hit = &cached[key];
+            assert_eq!(
+                (hit.min_temperature, hit.max_temperature, hit.count),
+                (data.min_temperature, data.max_temperature, data.count)
+            );
+            assert_eq!(hit.total_temperature, data.total_temperature);
+        }
+        // Another projection has an entry of its own.
+        assert!(lookup(&dir, &fingerprint, csv).unwrap().is_none());
+
+        // Same length and ends, but touched: the modification time makes it stale.
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        drop(file);
+        let touched = Fingerprint::of(&path).unwrap();
+        assert_ne!(touched, fingerprint);
+        assert!(lookup(&dir, &touched, None).unwrap().is_none());
+
+        // Rewritten with other contents of the same length.
+        std::fs::write(&path, input.replace("12.0", "13.0")).unwrap();
+        let rewritten = Fingerprint::of(&path).unwrap();
+        assert!(lookup(&dir, &rewritten, None).unwrap().is_none());
+
+        // A truncated entry is stale rather than an error.
+        let entry = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let data = std::fs::read(&entry).unwrap();
+        std::fs::write(&entry, &data[..data.len() - 1]).unwrap();
+        assert!(lookup(&dir, &fingerprint, None).unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,356 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use clap::Args;
+
+use super::output::{self, Format};
+use crate::obrc::{self, ConvertArgs};
+use crate::{
+    aggregate_file, finish, malformed, process_buffer, Aggregation, Config, WeatherData,
+    DEFAULT_CHUNK_SIZE,
+};
+
+#[derive(Args, Debug)]
+pub struct SelfTestArgs {
+    /// Also compare the rounding of every representable temperature and of means at several
+    /// precisions against an exact reference
+    #[arg(long)]
+    pub rounding_sweep: bool,
+}
+
+struct Fixture {
+    name: &'static str,
+    input: &'static str,
+    expected: &'static str,
+}
+
+/// Small inputs with known reports.
+const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "single line",
+        input: "Abha;12.3\n",
+        expected: "Station: \"Abha\", Min: 12.3, Mean: 12.3, Max: 12.3\n",
+    },
+    Fixture {
+        name: "station names",
+        input: "Abha;5.0\nX;-1.0\nSan José;12.5\nZürich;3.1\nŁódź;-0.3\n\
+                Washington D.C.;20.0\nAbha;-5.0\nA B;0.0\nX;-1.0\n",
+        expected: "Station: \"A B\", Min: 0, Mean: 0, Max: 0\n\
+                   Station: \"Abha\", Min: -5, Mean: 0, Max: 5\n\
+                   Station: \"San José\", Min: 12.5, Mean: 12.5, Max: 12.5\n\
+                   Station: \"Washington D.C.\", Min: 20, Mean: 20, Max: 20\n\
+                   Station: \"X\", Min: -1, Mean: -1, Max: -1\n\
+                   Station: \"Zürich\", Min: 3.1, Mean: 3.1, Max: 3.1\n\
+                   Station: \"Łódź\", Min: -0.3, Mean: -0.3, Max: -0.3\n",
+    },
+    Fixture {
+        name: "long station names",
+        input: "International Falls Airport East;-3.0\nInternational Falls Airport West;7.0\n\
+                Station012345678901234567890123456789012345678901234567890123456789012345678901234567890123456789012;-99.9\n\
+                International Falls Airport East;-5.0\n",
+        expected: "Station: \"International Falls Airport East\", Min: -5, Mean: -4, Max: -3\n\
+                   Station: \"International Falls Airport West\", Min: 7, Mean: 7, Max: 7\n\
+                   Station: \"Station012345678901234567890123456789012345678901234567890123456789012345678901234567890123456789012\", Min: -99.9, Mean: -99.9, Max: -99.9\n",
+    },
+    Fixture {
+        name: "rounding",
+        input: "Abha;1.1\nAbha;1.2\nAbha;1.2\nOslo;-1.1\nOslo;-1.2\nOslo;-1.2\n\
+                Lima;0.1\nLima;0.2\nLima;0.2\nLima;0.2\nNuuk;99.9\nNuuk;-99.9\n",
+        expected: "Station: \"Abha\", Min: 1.1, Mean: 1.2, Max: 1.2\n\
+                   Station: \"Lima\", Min: 0.1, Mean: 0.2, Max: 0.2\n\
+                   Station: \"Nuuk\", Min: -99.9, Mean: 0, Max: 99.9\n\
+                   Station: \"Oslo\", Min: -1.2, Mean: -1.2, Max: -1.1\n",
+    },
+];
+
+/// Lines per layout fixture, enough for a couple of chunk boundaries.
+const LAYOUT_LINES: usize = DEFAULT_CHUNK_SIZE / 4;
+
+/// Counts of values averaged by the rounding sweep.
+const SWEEP_COUNTS: [i64; 6] = [2, 3, 4, 7, 10, 100];
+
+/// Mismatches printed per sweep before only counting the rest.
+const SWEEP_EXAMPLES: usize = 5;
+
+/// Runs the embedded fixtures through the text and `.obrc` pipelines and prints PASS/FAIL
+/// for each, failing if any of them does.
+pub fn run(args: &SelfTestArgs) -> io::Result<()> {
+    let directory = std::env::temp_dir().join(format!("onebrc-self-test-{}", process::id()));
+    fs::create_dir_all(&directory)?;
+    let result = run_fixtures(&directory);
+    let _ = fs::remove_dir_all(&directory);
+
+    let mut failed = result?;
+    if args.rounding_sweep {
+        failed += rounding_sweep();
+    }
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} checks failed", failed)));
+    }
+    println!("All checks passed");
+    Ok(())
+}
+
+fn run_fixtures(directory: &Path) -> io::Result<usize> {
+    let mut failed = 0;
+    for fixture in FIXTURES {
+        let lines = fixture.input.lines().count() as u64;
+        failed += check(
+            directory,
+            fixture.name,
+            fixture.input,
+            fixture.expected,
+            lines,
+        )?;
+    }
+
+    // Shift every line across the chunk boundaries one byte at a time.
+    for shift in 1..=10 {
+        let first = "O".repeat(shift);
+        let mut input = format!("{};1.0\n", first);
+        for line in 0..LAYOUT_LINES {
+            input.push_str(if line % 2 == 0 {
+                "Abha;12.5\n"
+            } else {
+                "Lima;-4.5\n"
+            });
+        }
+        let expected = format!(
+            "Station: \"Abha\", Min: 12.5, Mean: 12.5, Max: 12.5\n\
+             Station: \"Lima\", Min: -4.5, Mean: -4.5, Max: -4.5\n\
+             Station: \"{}\", Min: 1, Mean: 1, Max: 1\n",
+            first
+        );
+        let name = format!("chunk boundary layout, shift {}", shift);
+        failed += check(directory, &name, &input, &expected, LAYOUT_LINES as u64 + 1)?;
+    }
+    Ok(failed)
+}
+
+/// Checks one input through both pipelines and returns how many of them failed.
+fn check(
+    directory: &Path,
+    name: &str,
+    input: &str,
+    expected: &str,
+    lines: u64,
+) -> io::Result<usize> {
+    let text = directory.join("fixture.txt");
+    let binary = directory.join("fixture.obrc");
+    fs::write(&text, input)?;
+    obrc::convert(&ConvertArgs {
+        input: text.clone(),
+        out: binary.clone(),
+        line_ending: malformed::LineEnding::Auto,
+    })?;
+
+    let mut failed = 0;
+    for (pipeline, path) in [("text", &text), ("obrc", &binary)] {
+        let Aggregation {
+            mut station_temperatures,
+            total_lines,
+            ..
+        } = aggregate_file(path, &Config::default())?;
+        let mut report = Vec::new();
+        let options = output::Options {
+            format: Format::Text,
+            pg_table: "",
+            compression: output::Compression::Zstd,
+            with_count: false,
+            percentiles: None,
+            sources: None,
+        };
+        output::write_report(&mut report, &options, &finish(&mut station_temperatures))?;
+        let report = String::from_utf8_lossy(&report);
+
+        if report == expected && total_lines == lines {
+            println!("PASS {} ({})", name, pipeline);
+        } else {
+            failed += 1;
+            println!("FAIL {} ({})", name, pipeline);
+            if total_lines != lines {
+                println!("  expected {} lines, got {}", lines, total_lines);
+            }
+            for (expected, actual) in expected.lines().zip(report.lines()) {
+                if expected != actual {
+                    println!("  expected: {}\n  actual:   {}", expected, actual);
+                    break;
+                }
+            }
+            if expected.lines().count() != report.lines().count() {
+                println!(
+                    "  expected {} stations, got {}",
+                    expected.lines().count(),
+                    report.lines().count()
+                );
+            }
+        }
+    }
+    Ok(failed)
+}
+
+/// Rounds `numerator / denominator` tenths to a whole number of tenths the way the challenge's
+/// reference implementation does (`Math.round`: to nearest, ties toward positive infinity),
+/// and formats it with one decimal.
+///
+/// Deliberately slow and unlike the fast path: the degrees are worked out by long division on
+/// the decimal digits, down to the hundredths plus whether anything remains, and rounded by
+/// looking at the hundredths digit.
+fn reference_round(numerator: i64, denominator: i64) -> String {
+    assert!(denominator > 0);
+    let negative = numerator < 0;
+    // Tenths over `denominator` are degrees over `10 * denominator`.
+    let divisor = 10 * denominator as u64;
+
+    // Long division, one digit brought down at a time: the integer digits, then two zeros
+    // brought down for the tenths and the hundredths.
+    let mut digits = Vec::new();
+    let mut remainder = 0u64;
+    let dividend = format!("{}", numerator.unsigned_abs());
+    for digit in dividend
+        .bytes()
+        .map(|byte| (byte - b'0') as u64)
+        .chain([0, 0])
+    {
+        remainder = remainder * 10 + digit;
+        digits.push((remainder / divisor) as u8);
+        remainder %= divisor;
+    }
+    let inexact = remainder != 0;
+    let hundredths = digits.pop().unwrap();
+
+    // `digits` now ends in the tenths. Away from zero for a positive value from .x5 on, for a
+    // negative one only past an exact .x5, which rounds toward positive infinity.
+    let up = match (negative, hundredths) {
+        (_, 6..) => true,
+        (false, 5) => true,
+        (true, 5) => inexact,
+        _ => false,
+    };
+    if up {
+        let mut position = digits.len();
+        loop {
+            if position == 0 {
+                digits.insert(0, 1);
+                break;
+            }
+            position -= 1;
+            if digits[position] == 9 {
+                digits[position] = 0;
+            } else {
+                digits[position] += 1;
+                break;
+            }
+        }
+    }
+
+    let tenth = digits.pop().unwrap();
+    let whole: String = digits
+        .iter()
+        .map(|digit| (b'0' + digit) as char)
+        .skip_while(|&digit| digit == '0')
+        .collect();
+    let whole = if whole.is_empty() {
+        "0".to_string()
+    } else {
+        whole
+    };
+    let sign = if negative && (whole != "0" || tenth != 0) {
+        "-"
+    } else {
+        ""
+    };
+    format!("{}{}.{}", sign, whole, tenth)
+}
+
+/// Compares the fast parse and rounding path against `reference_round` for every temperature
+/// in -99.9..=99.9 and for the mean of every possible sum at each of `SWEEP_COUNTS`.
+fn rounding_sweep() -> usize {
+    let mut failed = 0;
+
+    let mut mismatches = 0;
+    let inputs = (-999..=999i64)
+        .map(|tenths| {
+            let sign = if tenths < 0 { "-" } else { "" };
+            let (whole, tenth) = (tenths.abs() / 10, tenths.abs() % 10);
+            (format!("{}{}.{}", sign, whole, tenth), tenths)
+        })
+        .chain([("-0.0".to_string(), 0)]);
+    for (input, tenths) in inputs {
+        let line = format!("A;{}\n", input);
+        let (station_temperatures, _) = process_buffer(line.as_bytes());
+        let data = *station_temperatures.values().next().unwrap();
+        let expected = reference_round(tenths, 1);
+        for actual in [data.min(), data.max()] {
+            let actual = format!("{:.1}", actual);
+            if actual != expected {
+                mismatches += 1;
+                if mismatches <= SWEEP_EXAMPLES {
+                    println!("  input {}: expected {}, got {}", input, expected, actual);
+                }
+            }
+        }
+    }
+    failed += sweep_result("rounding sweep, temperatures", mismatches);
+
+    for count in SWEEP_COUNTS {
+        let mut mismatches = 0;
+        for sum in -999 * count..=999 * count {
+            let mut data = WeatherData {
+                total_temperature: sum,
+                count: count as u32,
+                min_temperature: 0,
+                max_temperature: 0,
+                mean_temperature: 0,
+            };
+            data.update_mean();
+            let expected = reference_round(sum, count);
+            let actual = format!("{:.1}", data.mean());
+            if actual != expected {
+                mismatches += 1;
+                if mismatches <= SWEEP_EXAMPLES {
+                    println!(
+                        "  mean of {} values summing to {}: expected {}, got {}",
+                        count,
+                        reference_round(sum, 1),
+                        expected,
+                        actual
+                    );
+                }
+            }
+        }
+        failed += sweep_result(&format!("rounding sweep, means of {}", count), mismatches);
+    }
+    failed
+}
+
+fn sweep_result(name: &str, mismatches: usize) -> usize {
+    if mismatches == 0 {
+        println!("PASS {}", name);
+        0
+    } else {
+        println!("FAIL {}: {} mismatches", name, mismatches);
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_rounds_ties_toward_positive_infinity() {
+        assert_eq!(reference_round(1235, 100), "1.2");
+        assert_eq!(reference_round(1235, 10), "12.4");
+        assert_eq!(reference_round(-1235, 10), "-12.3");
+        assert_eq!(reference_round(-1236, 10), "-12.4");
+        assert_eq!(reference_round(-5, 10), "0.0");
+        assert_eq!(reference_round(-6, 10), "-0.1");
+        assert_eq!(reference_round(9995, 10), "100.0");
+        assert_eq!(reference_round(-999, 1), "-99.9");
+        assert_eq!(reference_round(20, 3), "0.7");
+        assert_eq!(reference_round(-20, 3), "-0.7");
+    }
+}
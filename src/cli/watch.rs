@@ -0,0 +1,150 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+
+use ahash::AHashMap;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::log;
+
+/// Suffix of the marker a producer creates once it has finished writing `<file>`.
+pub const DONE_SUFFIX: &str = ".done";
+
+/// How a file that is still being written is told apart from a complete one.
+#[derive(Debug, Clone, Copy)]
+pub enum Readiness {
+    /// Ready once its size and modification time stopped changing for this long.
+    Quiescent(Duration),
+    /// Ready once a `<file>.done` marker exists next to it.
+    DoneMarker,
+}
+
+struct Candidate {
+    seen: Option<(u64, SystemTime)>,
+    changed_at: Instant,
+}
+
+/// Watches a directory through the platform notification API (inotify, FSEvents, ...) and
+/// hands out files once they are completely written.
+pub struct DirectoryWatcher {
+    directory: PathBuf,
+    readiness: Readiness,
+    events: Receiver<notify::Result<notify::Event>>,
+    candidates: AHashMap<PathBuf, Candidate>,
+    // Dropping the watcher stops the notifications.
+    _watcher: RecommendedWatcher,
+}
+
+impl DirectoryWatcher {
+    pub fn new(directory: &Path, readiness: Readiness) -> io::Result<DirectoryWatcher> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })
+        .map_err(io::Error::other)?;
+        watcher
+            .watch(directory, RecursiveMode::NonRecursive)
+            .map_err(io::Error::other)?;
+
+        let mut directory_watcher = DirectoryWatcher {
+            directory: directory.to_path_buf(),
+            readiness,
+            events,
+            candidates: AHashMap::new(),
+            _watcher: watcher,
+        };
+
+        // Files already sitting in the directory never produce an event.
+        for entry in fs::read_dir(directory)? {
+            directory_watcher.touch(&entry?.path());
+        }
+        Ok(directory_watcher)
+    }
+
+    /// Waits up to `timeout` for changes and returns the files that became ready, in name order.
+    pub fn ready(&mut self, timeout: Duration) -> io::Result<Vec<PathBuf>> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => self.handle(event),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("file watcher stopped"))
+            }
+        }
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
+
+        let now = Instant::now();
+        let mut ready = Vec::new();
+        self.candidates.retain(|path, candidate| {
+            let Ok(metadata) = fs::metadata(path) else {
+                // Moved away or deleted before it was ever complete.
+                return false;
+            };
+            let seen = metadata
+                .modified()
+                .ok()
+                .map(|mtime| (metadata.len(), mtime));
+            if seen != candidate.seen {
+                candidate.seen = seen;
+                candidate.changed_at = now;
+            }
+
+            let is_ready = match self.readiness {
+                Readiness::Quiescent(settle) => now - candidate.changed_at >= settle,
+                Readiness::DoneMarker => marker_path(path).exists(),
+            };
+            if is_ready {
+                ready.push(path.clone());
+            }
+            !is_ready
+        });
+
+        ready.sort();
+        Ok(ready)
+    }
+
+    fn handle(&mut self, event: notify::Result<notify::Event>) {
+        match event {
+            Ok(event) => event.paths.iter().for_each(|path| self.touch(path)),
+            Err(err) => log::info!("Watch error on {}: {}", self.directory.display(), err),
+        }
+    }
+
+    fn touch(&mut self, path: &Path) {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+        if name.starts_with('.') {
+            return;
+        }
+        if let Some(data_file) = name.strip_suffix(DONE_SUFFIX) {
+            // A new marker makes its data file worth another look.
+            let data_file = path.with_file_name(data_file);
+            if data_file.is_file() {
+                self.touch(&data_file);
+            }
+            return;
+        }
+        if !path.is_file() {
+            return;
+        }
+
+        let candidate = self
+            .candidates
+            .entry(path.to_path_buf())
+            .or_insert(Candidate {
+                seen: None,
+                changed_at: Instant::now(),
+            });
+        candidate.changed_at = Instant::now();
+    }
+}
+
+pub fn marker_path(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(DONE_SUFFIX);
+    PathBuf::from(marker)
+}
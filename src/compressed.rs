@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Compressed bytes read at a time ahead of the decoder.
+const READ_BUFFER_SIZE: usize = 1 << 20;
+
+/// How an input file is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    /// One or more gzip members, as `gzip` and `pigz` write them.
+    Gzip,
+    /// One or more zstd frames; several are decoded in parallel.
+    Zstd,
+}
+
+impl Format {
+    /// By the magic bytes, or by the extension for a file too short to have them.
+    pub fn detect(path: &Path) -> Format {
+        let mut magic = [0u8; 4];
+        let read = File::open(path).and_then(|mut file| file.read_exact(&mut magic));
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        match (read.is_ok(), extension) {
+            (true, _) if magic.starts_with(&GZIP_MAGIC) => Format::Gzip,
+            (true, _) if magic == ZSTD_MAGIC => Format::Zstd,
+            (false, Some("gz")) => Format::Gzip,
+            (false, Some("zst")) => Format::Zstd,
+            _ => Format::Plain,
+        }
+    }
+}
+
+/// The decompressed text of `path`, to stream into the chunker.
+pub fn decoder(path: &Path, format: Format) -> io::Result<Box<dyn Read>> {
+    decode(File::open(path)?, format)
+}
+
+/// The decompressed text of `reader`, a file or a download.
+pub fn decode(reader: impl Read + 'static, format: Format) -> io::Result<Box<dyn Read>> {
+    let reader = BufReader::with_capacity(READ_BUFFER_SIZE, reader);
+    Ok(match format {
+        Format::Plain => Box::new(reader),
+        Format::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Format::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
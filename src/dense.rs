@@ -0,0 +1,259 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use ahash::AHashSet;
+
+use crate::table::StationTable;
+use crate::{key_prefix, read_at, Key, Rows, StationTemperatures, WeatherData};
+
+/// Windows read from across the input to find its stations.
+const SAMPLE_WINDOWS: usize = 16;
+const SAMPLE_WINDOW_SIZE: usize = 1 << 20;
+/// Stations per bucket of the perfect hash; smaller buckets find a free seed sooner.
+const BUCKET_SIZE: usize = 4;
+/// Seeds tried for one bucket before the build gives up.
+const MAX_SEED: u32 = 1 << 20;
+
+/// A collision-free index over the stations found in a sample of the input, for `--map dense`.
+/// Every station maps to a slot of its own in two steps ("hash and displace"): its word picks
+/// a bucket, and the seed found for that bucket at build time picks the slot. A row then costs
+/// two multiplies and one name compare instead of a probe sequence, and the totals live in a
+/// flat array.
+pub struct Index {
+    seeds: Box<[u32]>,
+    keys: Box<[Key]>,
+    used: Box<[bool]>,
+    slot_bits: u32,
+}
+
+impl Index {
+    /// Samples `SAMPLE_WINDOWS` stretches of the input, or all of it when it is smaller, and
+    /// indexes the stations in them. An error when the sample holds no stations or two names
+    /// that `key_word` cannot tell apart.
+    pub fn sample(file_path: &Path, preloaded: Option<&[u8]>) -> io::Result<Index> {
+        let mut stations = AHashSet::new();
+        let mut collect = |window: &[u8], whole: bool| {
+            // Only whole lines: skip to the first newline and stop at the last one.
+            let start = match whole {
+                true => 0,
+                false => memchr::memchr(b'\n', window).map_or(window.len(), |i| i + 1),
+            };
+            let end = memchr::memrchr(b'\n', window).map_or(window.len(), |i| i + 1);
+            for (station_name, _) in Rows::new(&window[start..end.max(start)]) {
+                stations.insert(Key::new(key_prefix(station_name)));
+            }
+        };
+
+        let file = File::open(file_path)?;
+        let len = match preloaded {
+            Some(data) => data.len(),
+            None => file.metadata()?.len() as usize,
+        };
+        if len <= SAMPLE_WINDOWS * SAMPLE_WINDOW_SIZE {
+            match preloaded {
+                Some(data) => collect(data, true),
+                None => {
+                    let mut data = Vec::new();
+                    read_at(&file, &mut data, len, 0)?;
+                    collect(&data, true);
+                }
+            }
+        } else {
+            let mut buf = Vec::new();
+            for window in 0..SAMPLE_WINDOWS {
+                // Evenly spaced, the first at the start of the input and the last at its end.
+                let start = window * (len - SAMPLE_WINDOW_SIZE) / (SAMPLE_WINDOWS - 1);
+                match preloaded {
+                    Some(data) => collect(&data[start..start + SAMPLE_WINDOW_SIZE], window == 0),
+                    None => {
+                        buf.clear();
+                        read_at(&file, &mut buf, SAMPLE_WINDOW_SIZE, start as u64)?;
+                        collect(&buf, window == 0);
+                    }
+                }
+            }
+        }
+
+        let mut stations = stations.into_iter().collect::<Vec<_>>();
+        stations.sort_unstable();
+        Index::build(&stations)
+    }
+
+    /// Indexes `stations`, which must be distinct.
+    pub fn build(stations: &[Key]) -> io::Result<Index> {
+        if stations.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no stations in the sample",
+            ));
+        }
+        let mut words = stations
+            .iter()
+            .map(|key| key_word(key.as_bytes()))
+            .collect::<Vec<_>>();
+        words.sort_unstable();
+        if words.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "two sampled stations share the same leading and trailing bytes",
+            ));
+        }
+
+        let slot_bits = (stations.len() * 2).next_power_of_two().trailing_zeros();
+        let bucket_count = stations.len().div_ceil(BUCKET_SIZE).next_power_of_two();
+        let mut buckets = vec![Vec::new(); bucket_count];
+        for key in stations {
+            let word = key_word(key.as_bytes());
+            buckets[bucket(word, bucket_count)].push((word, *key));
+        }
+        // The largest buckets are the hardest to place, so they go first while most slots are
+        // still free.
+        let mut order = (0..bucket_count).collect::<Vec<_>>();
+        order.sort_unstable_by_key(|&bucket| std::cmp::Reverse(buckets[bucket].len()));
+
+        let mut seeds = vec![0; bucket_count].into_boxed_slice();
+        let mut keys = vec![Key::new(b""); 1 << slot_bits].into_boxed_slice();
+        let mut used = vec![false; 1 << slot_bits].into_boxed_slice();
+        let mut slots = Vec::with_capacity(BUCKET_SIZE * 4);
+        for bucket_index in order {
+            let members = &buckets[bucket_index];
+            if members.is_empty() {
+                break;
+            }
+            let Some(seed) = (0..MAX_SEED).find(|&seed| {
+                slots.clear();
+                members.iter().all(|&(word, _)| {
+                    let slot = slot(word, seed, slot_bits);
+                    let free = !used[slot] && !slots.contains(&slot);
+                    slots.push(slot);
+                    free
+                })
+            }) else {
+                return Err(io::Error::other("no collision-free seed for a bucket"));
+            };
+            seeds[bucket_index] = seed;
+            for (&(_, key), &slot) in members.iter().zip(&slots) {
+                keys[slot] = key;
+                used[slot] = true;
+            }
+        }
+
+        Ok(Index {
+            seeds,
+            keys,
+            used,
+            slot_bits,
+        })
+    }
+
+    /// Stations indexed, for the verbose log.
+    pub fn len(&self) -> usize {
+        self.used.iter().filter(|&&used| used).count()
+    }
+
+    /// The slot of `station_name`, `None` for a station the sample missed.
+    #[inline(always)]
+    fn lookup(&self, station_name: &[u8]) -> Option<usize> {
+        let word = key_word(station_name);
+        let seed = self.seeds[bucket(word, self.seeds.len())];
+        let slot = slot(word, seed, self.slot_bits);
+        (self.used[slot] && self.keys[slot].as_bytes() == station_name).then_some(slot)
+    }
+}
+
+/// Only the station count: the slots themselves would flood `Config`'s debug output.
+impl fmt::Debug for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Index")
+            .field("stations", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The first and last eight bytes of a name and its length in one word, so a lookup never
+/// reads the whole name before the final compare. Shorter names are read in overlapping
+/// halves, which together still cover every byte.
+#[inline(always)]
+fn key_word(name: &[u8]) -> u64 {
+    let len = name.len();
+    let (first, last) = if len >= 8 {
+        (read_u64(&name[..8]), read_u64(&name[len - 8..]))
+    } else if len >= 4 {
+        let word = read_u32(&name[..4]) | read_u32(&name[len - 4..]) << 32;
+        (word, 0)
+    } else if len > 0 {
+        let word = name[0] as u64 | (name[len / 2] as u64) << 8 | (name[len - 1] as u64) << 16;
+        (word, 0)
+    } else {
+        (0, 0)
+    };
+    mix(first) ^ last.rotate_left(17) ^ len as u64
+}
+
+#[inline(always)]
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[inline(always)]
+fn read_u32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes(bytes.try_into().unwrap()) as u64
+}
+
+#[inline(always)]
+fn mix(word: u64) -> u64 {
+    let word = word.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    word ^ (word >> 29)
+}
+
+#[inline(always)]
+fn bucket(word: u64, bucket_count: usize) -> usize {
+    (mix(word) >> 32) as usize & (bucket_count - 1)
+}
+
+#[inline(always)]
+fn slot(word: u64, seed: u32, slot_bits: u32) -> usize {
+    (mix(word ^ (seed as u64).wrapping_mul(0xff51_afd7_ed55_8ccd)) >> (64 - slot_bits)) as usize
+}
+
+/// Counterpart of `add_buffer` that aggregates into a flat array indexed by `index`; stations
+/// the sample missed go to the worker's `table`. `sketch` sees every row, as in `add_buffer`.
+pub fn process_buffer(
+    buf: &[u8],
+    index: &Index,
+    table: &mut StationTable,
+    mut sketch: impl FnMut(&[u8], i32),
+) -> (StationTemperatures, u32) {
+    let mut totals = vec![
+        WeatherData {
+            total_temperature: 0,
+            min_temperature: i32::MAX,
+            max_temperature: i32::MIN,
+            count: 0,
+            mean_temperature: 0,
+        };
+        index.keys.len()
+    ];
+    let mut lines_count = 0;
+    for (station_name, temperature) in Rows::new(buf) {
+        let station_name = key_prefix(station_name);
+        match index.lookup(station_name) {
+            Some(slot) => totals[slot].add_temperature(temperature),
+            None => {
+                table.add(station_name, temperature);
+            }
+        }
+        sketch(station_name, temperature);
+        lines_count += 1;
+    }
+
+    let mut station_temperatures = StationTemperatures::default();
+    for (key, data) in index.keys.iter().zip(totals) {
+        if data.count > 0 {
+            station_temperatures.insert(*key, data);
+        }
+    }
+    (station_temperatures, lines_count)
+}
@@ -0,0 +1,83 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::malformed::Category;
+
+/// Why an input could not be aggregated.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to open {}: {source}", path.display())]
+    Open { path: PathBuf, source: io::Error },
+    #[error("unable to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("{}: invalid line {line:?}", path.display())]
+    InvalidLine { path: PathBuf, line: String },
+    #[error("{}: {category} at {}: {line:?}", path.display(), position(*offset, *line_number))]
+    Malformed {
+        path: PathBuf,
+        offset: u64,
+        /// One-based, unknown for stdin.
+        line_number: Option<u64>,
+        category: Category,
+        line: String,
+    },
+    #[error("a worker thread panicked")]
+    WorkerPanicked,
+}
+
+/// Why an `AggregatorBuilder` refused its options.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BuildError {
+    #[error("at least one thread is needed")]
+    NoThreads,
+    #[error("a chunk size of {chunk_size} bytes is below the minimum of {min}")]
+    ChunkTooSmall { chunk_size: usize, min: usize },
+    #[error("the station and the temperature cannot both be column {0}")]
+    SameColumn(usize),
+    #[error("malformed lines are only checked in plain `<station>;<temperature>` rows")]
+    MalformedWithProjection,
+    #[error("the dense map samples plain `<station>;<temperature>` rows")]
+    DenseWithProjection,
+}
+
+impl Error {
+    #[doc(hidden)]
+    pub fn open(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Error {
+        move |source| Error::Open {
+            path: path.into(),
+            source,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn read(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Error {
+        move |source| Error::Read {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+/// Lets the subcommands that report `io::Error`s aggregate with `?`.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Open { ref source, .. } | Error::Read { ref source, .. } => {
+                io::Error::new(source.kind(), err.to_string())
+            }
+            Error::InvalidLine { .. } | Error::Malformed { .. } => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+            Error::WorkerPanicked => io::Error::other(err),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn position(offset: u64, line_number: Option<u64>) -> String {
+    match line_number {
+        Some(line_number) => format!("line {} (byte {})", line_number, offset),
+        None => format!("byte {}", offset),
+    }
+}
@@ -0,0 +1,234 @@
+// `include/onebrc.h` declares these; regenerate it with `cbindgen --output include/onebrc.h`
+// after changing them.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic;
+use std::path::Path;
+use std::ptr;
+
+use crate::{Aggregator, Report};
+
+/// The stations of one input, from `onebrc_process_file`. Free it with `onebrc_report_free`.
+pub struct OnebrcReport {
+    report: Report,
+    /// The station names as C strings, in the order of `report`.
+    names: Vec<CString>,
+}
+
+/// One station of an `OnebrcReport`, in degrees rounded to a tenth. `name` belongs to the
+/// report and lives as long as it does. A name holding a NUL byte has no C form: it comes out
+/// empty, and only `onebrc_report_at` reaches the station.
+#[repr(C)]
+pub struct OnebrcStation {
+    pub name: *const c_char,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub count: u32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::default();
+}
+
+/// Aggregates the measurements at `path`, a text or `.obrc` file, on `threads` workers, or the
+/// available cores within the container limits for 0. Returns NULL when it fails, and
+/// `onebrc_last_error` says why, a panic included.
+///
+/// # Safety
+///
+/// `path` is a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_process_file(
+    path: *const c_char,
+    threads: u32,
+) -> *mut OnebrcReport {
+    // A panic must not unwind into the caller's frames.
+    panic::catch_unwind(|| process_file(path, threads)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        set_last_error(format!("panicked: {}", message));
+        ptr::null_mut()
+    })
+}
+
+unsafe fn process_file(path: *const c_char, threads: u32) -> *mut OnebrcReport {
+    if path.is_null() {
+        set_last_error("path is NULL");
+        return ptr::null_mut();
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        set_last_error("path is not UTF-8");
+        return ptr::null_mut();
+    };
+    let mut builder = Aggregator::builder();
+    if threads > 0 {
+        builder = builder.threads(threads as usize);
+    }
+    let report = match builder.build() {
+        Ok(aggregator) => aggregator.process_file(Path::new(path)),
+        Err(err) => {
+            set_last_error(err);
+            return ptr::null_mut();
+        }
+    };
+    match report {
+        Ok(report) => {
+            let names = report
+                .iter()
+                // The empty name documented on `OnebrcStation`.
+                .map(|station| CString::new(station.name).unwrap_or_default())
+                .collect();
+            Box::into_raw(Box::new(OnebrcReport { report, names }))
+        }
+        Err(err) => {
+            set_last_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Stations in `report`.
+///
+/// # Safety
+///
+/// `report` is from `onebrc_process_file` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_report_len(report: *const OnebrcReport) -> usize {
+    (*report).names.len()
+}
+
+/// Lines aggregated into `report`.
+///
+/// # Safety
+///
+/// `report` is from `onebrc_process_file` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_report_lines(report: *const OnebrcReport) -> u64 {
+    (*report).report.lines
+}
+
+/// Writes the station at `index`, in order of name, to `out`. Returns false when `index` is
+/// past the last station.
+///
+/// # Safety
+///
+/// `report` is from `onebrc_process_file` and not yet freed, and `out` points to an
+/// `OnebrcStation`.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_report_at(
+    report: *const OnebrcReport,
+    index: usize,
+    out: *mut OnebrcStation,
+) -> bool {
+    let report = &*report;
+    if index >= report.names.len() {
+        return false;
+    }
+    *out = station_at(report, index);
+    true
+}
+
+/// Writes the station named `name` to `out`. Returns false when the input never mentions it.
+///
+/// # Safety
+///
+/// `report` is from `onebrc_process_file` and not yet freed, `name` is a NUL-terminated
+/// string and `out` points to an `OnebrcStation`.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_report_get(
+    report: *const OnebrcReport,
+    name: *const c_char,
+    out: *mut OnebrcStation,
+) -> bool {
+    let report = &*report;
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return false;
+    };
+    let Ok(index) = report
+        .report
+        .stations
+        .binary_search_by(|station| station.name.as_str().cmp(name))
+    else {
+        return false;
+    };
+    *out = station_at(report, index);
+    true
+}
+
+/// Frees `report`; NULL is ignored.
+///
+/// # Safety
+///
+/// `report` is NULL or from `onebrc_process_file`, and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_report_free(report: *mut OnebrcReport) {
+    if !report.is_null() {
+        drop(Box::from_raw(report));
+    }
+}
+
+/// Why the last `onebrc_process_file` on this thread returned NULL, or an empty string. The
+/// message lives until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn onebrc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
+
+fn station_at(report: &OnebrcReport, index: usize) -> OnebrcStation {
+    let station = &report.report.stations[index];
+    OnebrcStation {
+        name: report.names[index].as_ptr(),
+        min: station.min,
+        mean: station.mean,
+        max: station.max,
+        count: station.count,
+    }
+}
+
+fn set_last_error(err: impl ToString) {
+    let message = CString::new(err.to_string()).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn c_interface_reports_stations_and_failures() {
+        let path = std::env::temp_dir().join(format!("onebrc-ffi-test-{}", std::process::id()));
+        std::fs::write(&path, "Hamburg;12.0\nNul\0Town;1.5\nHamburg;-3.4\n").unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let mut station = OnebrcStation {
+            name: std::ptr::null(),
+            min: 0.0,
+            mean: 0.0,
+            max: 0.0,
+            count: 0,
+        };
+        unsafe {
+            let report = onebrc_process_file(c_path.as_ptr(), 2);
+            std::fs::remove_file(&path).unwrap();
+            assert!(!report.is_null());
+            assert_eq!(onebrc_report_len(report), 2);
+            assert_eq!(onebrc_report_lines(report), 3);
+            assert!(onebrc_report_get(report, c"Hamburg".as_ptr(), &mut station));
+            assert_eq!((station.min, station.max, station.count), (-3.4, 12.0, 2));
+            // The name with a NUL byte comes out empty, in its place in the order.
+            assert!(onebrc_report_at(report, 1, &mut station));
+            assert_eq!(CStr::from_ptr(station.name), c"");
+            assert_eq!(station.count, 1);
+            assert!(!onebrc_report_at(report, 2, &mut station));
+            onebrc_report_free(report);
+
+            assert!(onebrc_process_file(c_path.as_ptr(), 2).is_null());
+            let error = CStr::from_ptr(onebrc_last_error()).to_str().unwrap();
+            assert!(error.contains(path.to_str().unwrap()), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use memmap2::Mmap;
+use zstd::stream::read::Decoder;
+use zstd::zstd_safe;
+
+use crate::error::{Error, Result};
+use crate::sharded::{self, ShardedMap};
+use crate::sketch::Sketches;
+use crate::stats::Stats;
+use crate::{
+    heap, log, malformed, merge_into, merge_tree, process_lines, progress, signals, Aggregation,
+    Config, MapLayout, StationTemperatures, Worker,
+};
+
+/// Skippable frames, such as the seek table of the seekable format, have magic numbers
+/// 0x184D2A50 to 0x184D2A5F.
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+/// The bytes of a frame before its first newline and after its last one, which belong to lines
+/// that other frames finish.
+enum Edges {
+    Split {
+        head: Vec<u8>,
+        tail: Vec<u8>,
+    },
+    /// A frame without a newline, all of it inside one line.
+    Whole(Vec<u8>),
+}
+
+/// What a worker found in the frames it decoded.
+struct Decoded {
+    station_temperatures: StationTemperatures,
+    lines: u64,
+    /// Frame index, edges and decompressed length of every frame.
+    edges: Vec<(usize, Edges, usize)>,
+    phases: [Duration; 2],
+    sketches: Sketches,
+}
+
+/// Aggregates a zstd file of several frames, such as `pzstd` output, concatenated `.zst` files
+/// or the seekable format, with every worker decoding frames of its own. `None` for a file of
+/// a single frame, which only a stream decoder can read.
+///
+/// Workers claim frames from a shared counter and parse the whole lines of what they decode;
+/// the partial lines at the frame edges are set aside and joined in order at the end, since
+/// frames split the text anywhere.
+pub fn aggregate(file_path: &Path, config: &Config, workers: usize) -> Result<Option<Aggregation>> {
+    let file = File::open(file_path).map_err(Error::open(file_path))?;
+    // SAFETY: the mapping is read only and dropped before this returns. Truncating the file
+    // underneath it would fault, as with any mmap.
+    let data = unsafe { Mmap::map(&file) }.map_err(Error::read(file_path))?;
+    let frames = frames(&data).map_err(Error::read(file_path))?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    log::verbose!("zstd frames: {}", frames.len());
+
+    let workers = workers.min(frames.len());
+    let next_frame = AtomicUsize::new(0);
+    let sharded = (config.map == MapLayout::Sharded).then(|| ShardedMap::new(workers));
+    let progress = progress::bar(config.progress, Some(data.len() as u64));
+    let results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    decode_frames(
+                        &data,
+                        &frames,
+                        &next_frame,
+                        config,
+                        sharded.as_ref(),
+                        &progress,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().map_err(|_| Error::WorkerPanicked))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    progress.finish();
+
+    let mut worker_maps = Vec::with_capacity(workers + 2);
+    let mut worker_sketches = Vec::with_capacity(workers + 1);
+    let mut edges = Vec::with_capacity(frames.len());
+    let mut phases = [Duration::ZERO; 2];
+    let mut total_lines = 0;
+    for result in results {
+        let decoded = result.map_err(Error::read(file_path))?;
+        worker_maps.push(decoded.station_temperatures);
+        worker_sketches.push(decoded.sketches);
+        edges.extend(decoded.edges);
+        total_lines += decoded.lines;
+        phases[0] += decoded.phases[0];
+        phases[1] += decoded.phases[1];
+    }
+    // Frames are claimed in order and claimed frames always finish, so `edges` covers a prefix.
+    edges.sort_unstable_by_key(|&(index, _, _)| index);
+    let finished = edges.len() == frames.len();
+
+    let mut lines = Vec::new();
+    let mut carry = Vec::new();
+    let mut bytes_read = 0;
+    for (_, frame_edges, len) in edges {
+        bytes_read += len as u64;
+        match frame_edges {
+            Edges::Split { head, tail } => {
+                // Nothing to join when a frame ended right after a newline.
+                carry.extend_from_slice(&head);
+                if !carry.is_empty() {
+                    lines.append(&mut carry);
+                    lines.push(b'\n');
+                }
+                carry = tail;
+            }
+            Edges::Whole(frame) => carry.extend_from_slice(&frame),
+        }
+    }
+    let resume_offset = match finished {
+        true => {
+            // The last line, which has no newline.
+            lines.append(&mut carry);
+            None
+        }
+        false => Some(bytes_read - carry.len() as u64),
+    };
+    let mut worker = Worker::new(config);
+    let (station_temperatures, lines_count) = process_lines(&lines, 0, config, &mut worker);
+    total_lines += lines_count as u64;
+
+    let phase = heap::enter(heap::Phase::Merge);
+    worker_maps.push(station_temperatures);
+    worker_maps.push(worker.table.into_map());
+    worker_maps.extend(sharded.map(ShardedMap::into_map));
+    worker_sketches.push(worker.sketches);
+    let station_temperatures = merge_tree(worker_maps);
+    let sketches = merge_tree(worker_sketches);
+    drop(phase);
+
+    let stats = Stats {
+        workers,
+        bytes_read,
+        phases: vec![("decompress", phases[0]), ("parse", phases[1])],
+        counters: Vec::new(),
+    };
+
+    Ok(Some(Aggregation {
+        station_temperatures,
+        total_lines,
+        stats,
+        resume_offset,
+        malformed: malformed::Report::default(),
+        sketches,
+    }))
+}
+
+/// The data frames of a zstd file, found from their block headers without decoding them.
+fn frames(data: &[u8]) -> io::Result<Vec<Range<usize>>> {
+    let mut frames = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let len = zstd_safe::find_frame_compressed_size(&data[start..]).map_err(|code| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid zstd frame at byte {}: {}",
+                    start,
+                    zstd_safe::get_error_name(code)
+                ),
+            )
+        })?;
+        let magic = data[start..]
+            .get(..4)
+            .map_or(0, |magic| u32::from_le_bytes(magic.try_into().unwrap()));
+        if magic & !0xF != SKIPPABLE_MAGIC {
+            frames.push(start..start + len);
+        }
+        start += len;
+    }
+    Ok(frames)
+}
+
+/// A worker's share of `aggregate`: decodes frames until none are left, or the run is
+/// interrupted.
+fn decode_frames(
+    data: &[u8],
+    frames: &[Range<usize>],
+    next_frame: &AtomicUsize,
+    config: &Config,
+    sharded: Option<&ShardedMap>,
+    progress: &progress::ProgressBar,
+) -> io::Result<Decoded> {
+    let mut decoded = Decoded {
+        station_temperatures: StationTemperatures::default(),
+        lines: 0,
+        edges: Vec::new(),
+        phases: [Duration::ZERO; 2],
+        sketches: Sketches::default(),
+    };
+    let mut worker = Worker::new(config);
+    let mut buf = Vec::new();
+    while !signals::interrupted() {
+        signals::wait_while_paused();
+        let index = next_frame.fetch_add(1, Ordering::Relaxed);
+        let Some(frame) = frames.get(index) else {
+            break;
+        };
+
+        let decode_start = Instant::now();
+        let phase = heap::enter(heap::Phase::Read);
+        buf.clear();
+        Decoder::with_buffer(&data[frame.clone()])?
+            .single_frame()
+            .read_to_end(&mut buf)?;
+        drop(phase);
+        decoded.phases[0] += decode_start.elapsed();
+
+        let parse_start = Instant::now();
+        let (Some(first), Some(last)) = (memchr::memchr(b'\n', &buf), memchr::memrchr(b'\n', &buf))
+        else {
+            decoded
+                .edges
+                .push((index, Edges::Whole(buf.clone()), buf.len()));
+            progress.inc(frame.len() as u64);
+            continue;
+        };
+        let phase = heap::enter(heap::Phase::Parse);
+        let (chunk_temperatures, lines_count) =
+            process_lines(&buf[first + 1..last + 1], 0, config, &mut worker);
+        drop(phase);
+        decoded.lines += lines_count as u64;
+        let edges = Edges::Split {
+            head: buf[..first].to_vec(),
+            tail: buf[last + 1..].to_vec(),
+        };
+        decoded.edges.push((index, edges, buf.len()));
+
+        let phase = heap::enter(heap::Phase::Merge);
+        sharded::fold(
+            sharded,
+            &mut decoded.station_temperatures,
+            &mut worker.table,
+            chunk_temperatures,
+        );
+        drop(phase);
+        decoded.phases[1] += parse_start.elapsed();
+        progress.inc(frame.len() as u64);
+    }
+    merge_into(&mut decoded.station_temperatures, worker.table.into_map());
+    decoded.sketches = worker.sketches;
+    Ok(decoded)
+}
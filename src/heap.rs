@@ -0,0 +1,104 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Part of the pipeline allocations are attributed to. Counting needs the `heap-profile`
+/// feature; without it the markers cost a thread-local write and nothing is counted.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    Other,
+    Read,
+    Parse,
+    Merge,
+}
+
+const PHASES: [&str; 4] = ["other", "read", "parse", "merge"];
+
+static ALLOCATIONS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+static BYTES: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+thread_local! {
+    static CURRENT: Cell<Phase> = const { Cell::new(Phase::Other) };
+}
+
+/// Attributes the calling thread's allocations to `phase` until the guard is dropped.
+pub fn enter(phase: Phase) -> PhaseGuard {
+    PhaseGuard {
+        previous: CURRENT.replace(phase),
+    }
+}
+
+pub struct PhaseGuard {
+    previous: Phase,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        CURRENT.set(self.previous);
+    }
+}
+
+/// `(phase, allocations, bytes)` since the last call.
+pub fn take() -> Vec<(&'static str, u64, u64)> {
+    PHASES
+        .iter()
+        .zip(ALLOCATIONS.iter().zip(&BYTES))
+        .map(|(name, (allocations, bytes))| {
+            (
+                *name,
+                allocations.swap(0, Ordering::Relaxed),
+                bytes.swap(0, Ordering::Relaxed),
+            )
+        })
+        .collect()
+}
+
+pub fn print() {
+    eprintln!("Heap allocations:");
+    for (phase, allocations, bytes) in take() {
+        eprintln!(
+            "  {:<10} {:>12} allocations {:>16} bytes",
+            phase, allocations, bytes
+        );
+    }
+}
+
+#[cfg(feature = "heap-profile")]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::Ordering;
+
+    use super::{ALLOCATIONS, BYTES, CURRENT};
+
+    pub struct Counting;
+
+    fn record(size: usize) {
+        // The thread local is gone while a thread tears down; count those as `other`.
+        let phase = CURRENT.try_with(|phase| phase.get() as usize).unwrap_or(0);
+        ALLOCATIONS[phase].fetch_add(1, Ordering::Relaxed);
+        BYTES[phase].fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            record(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            record(layout.size());
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            record(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+}
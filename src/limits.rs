@@ -0,0 +1,137 @@
+use std::fmt;
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Container resource limits, from the cgroup the process runs in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub cgroup: Option<&'static str>,
+    /// CPU quota divided by its period, e.g. 2.0 for a two CPU pod.
+    pub cpus: Option<f64>,
+    pub memory: Option<u64>,
+}
+
+impl Limits {
+    #[cfg(target_os = "linux")]
+    pub fn detect() -> Limits {
+        let Ok(membership) = fs::read_to_string("/proc/self/cgroup") else {
+            return Limits::default();
+        };
+
+        if Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+            let path = cgroup_path(&membership, "");
+            return Limits {
+                cgroup: Some("v2"),
+                cpus: min_over_ancestors("/sys/fs/cgroup", &path, |dir| {
+                    let cpu_max = read(&dir.join("cpu.max"))?;
+                    let (quota, period) = cpu_max.split_once(' ')?;
+                    Some(quota.parse::<f64>().ok()? / period.parse::<f64>().ok()?)
+                }),
+                memory: min_over_ancestors("/sys/fs/cgroup", &path, |dir| {
+                    read(&dir.join("memory.max"))?.parse().ok()
+                }),
+            };
+        }
+
+        let cpu_path = cgroup_path(&membership, "cpu");
+        let memory_path = cgroup_path(&membership, "memory");
+        Limits {
+            cgroup: Some("v1"),
+            cpus: min_over_ancestors("/sys/fs/cgroup/cpu", &cpu_path, |dir| {
+                let quota: f64 = read(&dir.join("cpu.cfs_quota_us"))?.parse().ok()?;
+                let period: f64 = read(&dir.join("cpu.cfs_period_us"))?.parse().ok()?;
+                // A quota of -1 means unlimited.
+                (quota > 0.0).then(|| quota / period)
+            }),
+            memory: min_over_ancestors("/sys/fs/cgroup/memory", &memory_path, |dir| {
+                let limit: u64 = read(&dir.join("memory.limit_in_bytes"))?.parse().ok()?;
+                // Unlimited shows up as a page-aligned i64::MAX.
+                (limit < 1 << 62).then_some(limit)
+            }),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn detect() -> Limits {
+        Limits::default()
+    }
+
+    /// Workers to run: the cores `available_parallelism` reports, capped by the CPU quota and
+    /// by what the memory limit leaves room for when each has `chunk_bytes` in flight.
+    pub fn workers(&self, chunk_bytes: usize) -> usize {
+        let mut workers = thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(1);
+        if let Some(cpus) = self.cpus {
+            workers = workers.min(cpus.ceil() as usize);
+        }
+        if let Some(memory) = self.memory {
+            // Leave three quarters of the limit for the maps, the merge and the page cache.
+            workers = workers.min(memory as usize / 4 / chunk_bytes.max(1));
+        }
+        workers.max(1)
+    }
+}
+
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(cgroup) = self.cgroup else {
+            return write!(f, "no cgroup limits");
+        };
+        write!(f, "cgroup {}", cgroup)?;
+        match self.cpus {
+            Some(cpus) => write!(f, ", {:.1} CPUs", cpus)?,
+            None => write!(f, ", no CPU quota")?,
+        }
+        match self.memory {
+            Some(memory) => write!(f, ", {} MiB memory", memory >> 20),
+            None => write!(f, ", no memory limit"),
+        }
+    }
+}
+
+/// Path of the cgroup holding `controller` (v1), or of the unified hierarchy for `""` (v2).
+#[cfg(target_os = "linux")]
+fn cgroup_path(membership: &str, controller: &str) -> PathBuf {
+    membership
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+            let matches = if controller.is_empty() {
+                controllers.is_empty()
+            } else {
+                controllers.split(',').any(|name| name == controller)
+            };
+            matches.then(|| PathBuf::from(path.trim_start_matches('/')))
+        })
+        .next()
+        .unwrap_or_default()
+}
+
+/// Smallest limit set on the cgroup or any of its ancestors below `mount`. Inside a container
+/// the host path usually does not exist, and only the mount root is left to look at.
+#[cfg(target_os = "linux")]
+fn min_over_ancestors<T: PartialOrd>(
+    mount: &str,
+    path: &Path,
+    limit: impl Fn(&Path) -> Option<T>,
+) -> Option<T> {
+    let mount = Path::new(mount);
+    path.ancestors()
+        .filter_map(|ancestor| limit(&mount.join(ancestor)))
+        .fold(None, |min, limit| match min {
+            Some(min) if min <= limit => Some(min),
+            _ => Some(limit),
+        })
+}
+
+#[cfg(target_os = "linux")]
+fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much goes to stderr besides errors, which are always printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Only the report and errors.
+    Quiet,
+    /// Warnings and the diagnostics a flag asked for, such as `--stats`.
+    Normal,
+    /// Also the per-run details: workers, chunk size, chunk count, line counts and timings.
+    Verbose,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+pub fn set(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+/// `eprintln!` unless `--quiet` is set.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Normal) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// `eprintln!` only when `--verbose` is set.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// Exported at the crate root as macros must be, and used through this module as `log::info!`.
+pub use crate::{log_info as info, log_verbose as verbose};
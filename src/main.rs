@@ -1,275 +1,976 @@
-/*
-* Input value ranges are as follows:
-1. Station name: non null UTF-8 string of min length 1 character and max length 100 bytes,
-    containing neither ; nor \n characters. (i.e. this could be 100 one-byte characters,
-    or 50 two-byte characters, etc.)
-2. Temperature value: non null double between -99.9 (inclusive) and 99.9 (inclusive),
-    always with one fractional digit
-* There is a maximum of 10,000 unique station names
-* Line endings in the file are \n characters on all platforms
-* Implementations must not rely on specifics of a given data set,
-    e.g. any valid station name as per the constraints above and
-    any data distribution (number of measurements per station) must be supported
-* The rounding of output values must be done using the semantics of
-    IEEE 754 rounding-direction "roundTowardPositive"
-*/
-
-use ahash::AHashMap;
-use std::str;
-use std::sync::atomic::AtomicU32;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::thread;
 use std::time;
-use std::{
-    fs::File,
-    io::{Read, Seek, SeekFrom},
+
+use clap::{Args, Parser, Subcommand};
+#[cfg(feature = "duckdb")]
+use onebrc::cli::duckdb_sink;
+use onebrc::cli::{
+    ab, cache, concat, daemon, hooks, inputs, inspect, mapbench, measure, output, priority,
+    result_cache, selftest,
+};
+use onebrc::projection::{self, Column, InputFormat, Projection};
+use onebrc::sketch::{self, Percentile, Sketches};
+use onebrc::stats::Stats;
+use onebrc::{
+    aggregate_files, aggregate_sources, compressed, finish, heap, is_parquet, is_remote, is_stdin,
+    log, malformed, obrc, preload, signals, stats, Aggregation, Config, Engine, MapLayout, Mode,
+    StationTemperatures, MIN_CHUNK_SIZE, WIDE_ROW_SIZE,
 };
 
-#[derive(Debug, Clone, Copy)]
-struct WeatherData {
-    total_temperature: f32,
-    min_temperature: f32,
-    max_temperature: f32,
-    count: u32,
-    mean_temperature: f32,
-}
+#[derive(Parser, Debug)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-impl WeatherData {
-    #[inline(always)]
-    fn merge(&mut self, other: &WeatherData) {
-        self.total_temperature += other.total_temperature;
-        self.count += other.count;
-        self.min_temperature = self.min_temperature.min(other.min_temperature);
-        self.max_temperature = self.max_temperature.max(other.max_temperature);
-    }
+    /// Without a subcommand the arguments of `run` apply
+    #[command(flatten)]
+    run: RunArgs,
 
-    #[inline(always)]
-    fn add_temperature(&mut self, temperature: f32) {
-        self.min_temperature = self.min_temperature.min(temperature);
-        self.max_temperature = self.max_temperature.max(temperature);
-        self.total_temperature += temperature;
-        self.count += 1;
-    }
+    /// Shell command run after a successful run, with ONEBRC_* variables describing it
+    #[arg(long, global = true, value_name = "CMD")]
+    on_complete: Option<String>,
 
-    #[inline(always)]
-    fn update_mean(&mut self) {
-        self.mean_temperature = self.total_temperature / self.count as f32;
-    }
+    /// Shell command run when the run fails
+    #[arg(long, global = true, value_name = "CMD")]
+    on_error: Option<String>,
 
-    #[inline(always)]
-    fn round(&mut self) {
-        self.mean_temperature = (self.mean_temperature * 10.0).round() / 10.0;
-        self.min_temperature = (self.min_temperature * 10.0).round() / 10.0;
-        self.max_temperature = (self.max_temperature * 10.0).round() / 10.0;
-    }
-}
+    /// Also print per-run details: workers, chunk size, chunk count, line counts and timings
+    #[arg(long, short, global = true, conflicts_with = "quiet")]
+    verbose: bool,
 
-const KEY_SIZE: usize = 16;
-type Key = [u8; KEY_SIZE];
-type StationTemperatures = AHashMap<Key, WeatherData>;
+    /// Print nothing but the report and errors
+    #[arg(long, short, global = true)]
+    quiet: bool,
+}
 
-fn process_weather_line(line: &str) -> (Key, WeatherData) {
-    let parts: Vec<&str> = line.split(';').collect();
-    if parts.len() != 2 || line.is_empty() {
-        panic!("Invalid line");
-    }
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Measurements files to aggregate into one report, each text, gzip, zstd or `.obrc`, `-` for
+    /// text on stdin, or an `https://` or `s3://` URL (`remote` feature). Globs such as
+    /// `'data/part-*.txt'` and directories expand to the files they hold, sorted by path
+    /// [default: measurements.txt]
+    inputs: Vec<PathBuf>,
+
+    /// Another input, aggregated after the positional ones; may be repeated
+    #[arg(long = "input", value_name = "PATH")]
+    more_inputs: Vec<PathBuf>,
+
+    /// Keep the stations of every input apart, reporting them by (source, station) with the
+    /// input in a `source` column
+    #[arg(long, conflicts_with_all = ["cache_dir", "histogram"])]
+    tag_by_source: bool,
+
+    /// Report format written to stdout
+    #[arg(long, value_enum, default_value_t = output::Format::Text)]
+    format: output::Format,
+
+    /// Write the report to this file instead of stdout, replacing it atomically
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Table named in the `COPY` statement of the pg-copy format, used verbatim
+    #[arg(long, default_value = "stations")]
+    pg_table: String,
+
+    /// Order of the stations in the report
+    #[arg(long, value_enum, default_value_t = output::SortBy::Name)]
+    sort_by: output::SortBy,
+
+    /// Sort from the highest value, or from the end of the alphabet, down
+    #[arg(long)]
+    desc: bool,
+
+    /// Report only the N stations with the highest `--sort-by` value
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// Report only the N stations with the lowest `--sort-by` value; with `--top`, both ends
+    #[arg(long, value_name = "N")]
+    bottom: Option<usize>,
+
+    /// Add the number of measurements per station to the text, challenge, table and md
+    /// formats; the others always have it
+    #[arg(long)]
+    with_count: bool,
+
+    /// Codec compressing the columns of the parquet format
+    #[arg(long, value_enum, default_value_t = output::Compression::Zstd)]
+    parquet_compression: output::Compression,
+
+    /// Column holding the station name, for rows with extra fields: a zero-based index, or a
+    /// name looked up in the header row of the first input
+    #[arg(long, value_name = "COLUMN", requires = "value_col")]
+    station_col: Option<Column>,
+
+    /// Column holding the temperature, an index or a header name like `--station-col`
+    #[arg(
+        long,
+        visible_alias = "temp-col",
+        value_name = "COLUMN",
+        requires = "station_col"
+    )]
+    value_col: Option<Column>,
+
+    /// How the fields of the rows `--station-col` picks from are delimited
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InputFormat::Measurements,
+        requires_if("csv", "station_col")
+    )]
+    input_format: InputFormat,
+
+    /// Worker threads [default: the available cores, capped by the cgroup limits]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+
+    /// Bytes each worker reads per chunk, with an optional K, M or G (binary) suffix
+    #[arg(long, value_name = "SIZE", default_value = "2000000", value_parser = parse_size)]
+    chunk_size: usize,
+
+    /// Bytes each chunk reads past its end to finish its last row [default: 128, or 1024 with
+    /// --station-col]
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..=WIDE_ROW_SIZE as u64))]
+    overlap: Option<u64>,
+
+    /// Run only part of the text pipeline, for benchmarking it in isolation
+    #[arg(long, value_enum, default_value_t = Mode::Full)]
+    mode: Mode,
+
+    /// How the workers keep the station totals
+    #[arg(long, value_enum, default_value_t = MapLayout::PerWorker)]
+    map: MapLayout,
+
+    /// Time this many runs and report their spread; the report comes from the last one
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: u32,
+
+    /// Report only the best and median time of the `--repeat` runs, as leaderboards do
+    #[arg(long)]
+    report_best: bool,
+
+    /// Untimed runs before the timed ones, to warm the page cache
+    #[arg(long, value_name = "M", default_value_t = 0)]
+    warmup: u32,
+
+    /// Time two strategies against each other, taking turns for `--warmup` and `--repeat`
+    /// runs each, and fail unless their results match exactly. A strategy is an `--engine`, a
+    /// `--map` or both joined by `+`, such as `read,mmap+dense`
+    #[arg(
+        long,
+        value_name = "A,B",
+        value_parser = ab::parse_pair,
+        conflicts_with_all = ["engine", "map", "preload", "cache_dir", "tag_by_source"]
+    )]
+    ab: Option<[ab::Strategy; 2]>,
+
+    /// Niceness of the worker threads, from -20 (highest priority) to 19 (lowest)
+    #[arg(long, value_name = "N", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nice: Option<i32>,
+
+    /// Priority of the worker threads, a shorthand for common `--nice` values
+    #[arg(long, value_enum, conflicts_with = "nice")]
+    thread_priority: Option<priority::ThreadPriority>,
+
+    /// Evict the input from the page cache before every timed run (Linux only)
+    #[arg(long, conflicts_with = "preload")]
+    drop_caches: bool,
+
+    /// How chunks are read from an input file
+    #[arg(long, value_enum, default_value_t = Engine::Read, conflicts_with = "preload")]
+    engine: Engine,
+
+    /// Read the whole input into memory before the timed runs, so they measure parsing alone
+    #[arg(long)]
+    preload: bool,
+
+    /// Largest share of the available memory `--preload` may take; larger inputs are mapped
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value_t = 0.8,
+        requires = "preload"
+    )]
+    preload_limit: f64,
+
+    /// Reuse the result of an earlier run over the same, unchanged input from this directory
+    /// (default `~/.cache/onebrc`), and store new results there
+    #[arg(long, value_name = "DIR", num_args = 0..=1)]
+    cache_dir: Option<Option<PathBuf>>,
+
+    /// Scan the input even when the cache holds its result, and refresh the entry
+    #[arg(long, requires = "cache_dir")]
+    no_cache: bool,
+
+    /// Check every line and skip or stop at the ones that are not `<station>;<temperature>`
+    /// with a number in -99.9..=99.9. Without it lines are not checked, which is fastest
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ACTION",
+        conflicts_with = "station_col"
+    )]
+    on_malformed: Option<malformed::Action>,
+
+    /// Also hold every line to the rest of the 1BRC input contract: names of 1 to 100 bytes
+    /// and temperatures with exactly one fractional digit. Stops at the first line that breaks
+    /// it unless `--on-malformed skip` is given
+    #[arg(long, conflicts_with = "station_col")]
+    strict: bool,
+
+    /// Whether the checks of `--on-malformed` and `--strict` take a `\r` before the newline
+    /// for part of the line end, or hold it to the 1BRC contract of `\n` alone. Unchecked
+    /// runs always read `\r\n` as a newline
+    #[arg(long, value_enum, default_value_t = malformed::LineEnding::Auto, value_name = "ENDING")]
+    line_ending: malformed::LineEnding,
+
+    /// Write every line `--on-malformed skip` left out to this file, with its byte offset
+    #[arg(long, value_name = "PATH", requires = "on_malformed")]
+    malformed_log: Option<PathBuf>,
+
+    /// Write exact per-station counts of every temperature, one `<station>.csv` per station,
+    /// to this directory
+    #[arg(long, value_name = "DIR")]
+    histogram: Option<PathBuf>,
+
+    /// Show a progress bar with throughput and the estimated time left
+    #[arg(long)]
+    progress: bool,
+
+    /// Print how the workers split their time between reading and parsing; `detailed` adds
+    /// hardware counters. Percentiles such as `p50,p99` add approximate ones per station to
+    /// the report instead
+    #[arg(
+        long,
+        value_name = "STATS",
+        num_args = 0..=1,
+        value_delimiter = ',',
+        default_missing_value = "basic"
+    )]
+    stats: Vec<stats::Request>,
+
+    /// Print one line of figures over the whole input: rows, stations, the extremes and the
+    /// stations they belong to, the mean, and the bytes read and how fast
+    #[arg(long)]
+    summary: bool,
+
+    /// Append the results to this DuckDB database
+    #[cfg(feature = "duckdb")]
+    #[arg(long, value_name = "DATABASE")]
+    output_duckdb: Option<PathBuf>,
+
+    /// Table the DuckDB rows are appended to
+    #[cfg(feature = "duckdb")]
+    #[arg(long, default_value = "runs", requires = "output_duckdb")]
+    table: String,
+}
 
-    let mut key = [0u8; KEY_SIZE];
-    let name = parts[0].as_bytes();
-    let station_length = name.len().min(KEY_SIZE);
-    key[..station_length].copy_from_slice(&name[..station_length]);
-    let temperature = parts[1].parse::<f32>().unwrap();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Aggregate a measurements file, the default when no subcommand is given
+    Run(Box<RunArgs>),
+    /// Concatenate measurement shards into a single file
+    Concat(concat::ConcatArgs),
+    /// Convert a measurements text file to the binary `.obrc` format
+    Convert(obrc::ConvertArgs),
+    /// Aggregate files dropped into a spool directory into a running snapshot
+    Daemon(Box<daemon::DaemonArgs>),
+    /// Guess the layout of a delimited file and print the flags needed to aggregate it
+    Inspect(inspect::InspectArgs),
+    /// Check the build against small embedded fixtures with known results
+    SelfTest(selftest::SelfTestArgs),
+}
 
-    let weather_data = WeatherData {
-        total_temperature: temperature,
-        count: 1,
-        min_temperature: temperature,
-        max_temperature: temperature,
-        mean_temperature: 0.0,
+/// Parses `2000000`, `512K`, `8M` or `1G` (also `8MiB`, `8mb`) into bytes.
+fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, suffix) = size.split_at(digits);
+    let number: usize = number
+        .parse()
+        .map_err(|_| format!("`{}` does not start with a number", size))?;
+    let shift = match suffix.to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        _ => {
+            return Err(format!(
+                "unknown size suffix `{}`, expected K, M or G",
+                suffix
+            ))
+        }
     };
-
-    (key, weather_data)
+    let bytes = number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("`{}` is too large", size))?;
+    if bytes < MIN_CHUNK_SIZE {
+        return Err(format!("must be at least {} bytes", MIN_CHUNK_SIZE));
+    }
+    Ok(bytes)
 }
 
-#[inline(always)]
-fn process_buffer(buf: &[u8]) -> (StationTemperatures, u32) {
-    let mut station_temperatures: StationTemperatures = AHashMap::with_capacity(1000);
-    let mut station_name = [0u8; KEY_SIZE];
-    let mut temperature = 0.0;
-    let mut lines_count = 0;
-    let mut negative_multiplier = 1;
-    let mut state = 0;
-    let mut station_index = 0;
-
-    buf.iter().enumerate().for_each(|(index, &byte)| {
-        if byte == b';' {
-            state = 1;
-        } else if state == 0 && station_index < KEY_SIZE {
-            station_name[station_index] = byte;
-            station_index += 1;
-        } else if byte == b'.' {
-            temperature = temperature + (u8::from(buf[index + 1]) - 48) as f32 * 0.1;
-            temperature = temperature * negative_multiplier as f32;
-            state = 2;
-        } else if byte == b'-' {
-            negative_multiplier = -1;
-        } else if state == 1 {
-            temperature = temperature * 10.0 + (u8::from(byte) - 48) as f32;
-        } else if byte == b'\n' {
-            if let Some(data) = station_temperatures.get_mut(&station_name) {
-                data.add_temperature(temperature);
-            } else {
-                station_temperatures.insert(
-                    station_name,
-                    WeatherData {
-                        total_temperature: temperature,
-                        count: 1,
-                        min_temperature: temperature,
-                        max_temperature: temperature,
-                        mean_temperature: 0.0,
-                    },
-                );
-            }
+/// Input and output of an aggregation, as the hooks report them.
+fn run_labels(args: &RunArgs) -> (String, String) {
+    let output = args
+        .output
+        .as_ref()
+        .map_or("-".to_string(), |path| path.display().to_string());
+    let input = input_patterns(args)
+        .iter()
+        .map(|input| input.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (input, output)
+}
 
-            lines_count += 1;
-            station_name.fill(0);
-            temperature = 0.0;
-            negative_multiplier = 1;
-            state = 0;
-            station_index = 0;
-        }
+fn main() {
+    let cli = Cli::parse();
+    log::set(match (cli.verbose, cli.quiet) {
+        (true, _) => log::Level::Verbose,
+        (_, true) => log::Level::Quiet,
+        _ => log::Level::Normal,
     });
 
-    return (station_temperatures, lines_count);
-}
-
-fn process_thread(buf: &[u8], extra_buffer_size: usize) -> (StationTemperatures, u32) {
-    let start_index = buf
-        .iter()
-        .position(|&b| b == b'\n')
-        .map(|i| i + 1)
-        .unwrap_or(0);
+    let (input, output) = match &cli.command {
+        Some(Command::Concat(args)) => (
+            args.shards
+                .iter()
+                .map(|shard| shard.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            args.out.display().to_string(),
+        ),
+        Some(Command::Convert(args)) => (
+            args.input.display().to_string(),
+            args.out.display().to_string(),
+        ),
+        Some(Command::Daemon(args)) => (
+            args.spool
+                .iter()
+                .chain(args.input.iter())
+                .map(|input| input.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            args.snapshot.display().to_string(),
+        ),
+        Some(Command::Inspect(args)) => (args.input.display().to_string(), "-".to_string()),
+        Some(Command::SelfTest(_)) => ("-".to_string(), "-".to_string()),
+        Some(Command::Run(args)) => run_labels(args),
+        None => run_labels(&cli.run),
+    };
+    hooks::install(cli.on_complete.clone(), cli.on_error.clone(), input, output);
 
-    let buf_default_pos = buf.len() - extra_buffer_size;
-    let end_index = buf[buf_default_pos..]
-        .iter()
-        .position(|&b| b == b'\n')
-        .map(|i| i + buf_default_pos + 1)
-        .unwrap_or(buf_default_pos);
+    match &cli.command {
+        Some(Command::Concat(args)) => {
+            if let Err(err) = concat::run(args) {
+                eprintln!("concat failed: {}", err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        Some(Command::Convert(args)) => {
+            if let Err(err) = obrc::convert(args) {
+                eprintln!("convert failed: {}", err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        Some(Command::Daemon(args)) => {
+            if let Err(err) = daemon::run(args) {
+                eprintln!("daemon failed: {}", err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        Some(Command::Inspect(args)) => {
+            if let Err(err) = inspect::run(args) {
+                eprintln!("inspect failed: {}", err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        Some(Command::SelfTest(args)) => {
+            if let Err(err) = selftest::run(args) {
+                eprintln!("self-test failed: {}", err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        Some(Command::Run(args)) => aggregate(args),
+        None => aggregate(&cli.run),
+    }
 
-    process_buffer(&buf[start_index..end_index])
+    hooks::complete();
 }
 
-const TOTAL_LINES: usize = 1_000_000_000;
-const AVG_ROW_SIZE: usize = 14;
-const THREAD_COUNT: usize = 250;
-const BUFFER_SIZE: usize = 2_000_000;
-const STAGE_COUNT: usize = (TOTAL_LINES * AVG_ROW_SIZE).div_ceil(THREAD_COUNT * BUFFER_SIZE);
-const SINGLE_ROW_SIZE: usize = 64;
-
-fn main() {
-    let start_time = time::Instant::now();
+/// The inputs as given, before `inputs::expand`.
+fn input_patterns(args: &RunArgs) -> Vec<PathBuf> {
+    match (args.inputs.as_slice(), args.more_inputs.as_slice()) {
+        ([], []) => vec![PathBuf::from("measurements.txt")],
+        (inputs, more_inputs) => inputs.iter().chain(more_inputs).cloned().collect(),
+    }
+}
 
-    println!("buffer size: {:?}", BUFFER_SIZE);
+fn aggregate(cli: &RunArgs) {
+    let inputs = inputs::expand(&input_patterns(cli)).unwrap_or_else(|err| {
+        eprintln!("Unable to expand the inputs: {}", err);
+        hooks::fail(1, &err.to_string())
+    });
+    let inputs = inputs.as_slice();
+    if inputs.len() > 1 {
+        log::verbose!("inputs: {}", inputs.len());
+    }
+    // Fail up front on inputs that cannot work, rather than with a panic from whichever reader
+    // opens them first.
+    if inputs.len() > 1 {
+        // Each of these works on one file.
+        let single_only = [
+            ("--preload", cli.preload),
+            ("--cache-dir", cli.cache_dir.is_some()),
+            ("--mode map-bench", cli.mode == Mode::MapBench),
+        ];
+        if let Some((flag, _)) = single_only.iter().find(|(_, set)| *set) {
+            eprintln!("{} needs a single input", flag);
+            hooks::fail(2, &format!("{} with several inputs", flag));
+        }
+    }
+    for file_path in inputs {
+        check_input(cli, file_path);
+    }
+    let feature = match cli.format {
+        output::Format::Arrow if cfg!(not(feature = "arrow")) => Some("arrow"),
+        output::Format::Parquet if cfg!(not(feature = "parquet")) => Some("parquet"),
+        _ => None,
+    };
+    if let Some(feature) = feature {
+        eprintln!(
+            "--format {} needs a build with `--features {}`",
+            feature, feature
+        );
+        hooks::fail(
+            2,
+            &format!("--format {} without the {} feature", feature, feature),
+        );
+    }
+    if cli.tag_by_source {
+        // Neither has a place for the source of a station.
+        let untagged = [
+            (
+                "--format challenge",
+                cli.format == output::Format::Challenge,
+            ),
+            ("--stats with percentiles", !percentiles(cli).is_empty()),
+        ];
+        if let Some((flag, _)) = untagged.iter().find(|(_, set)| *set) {
+            eprintln!("{} cannot be combined with --tag-by-source", flag);
+            hooks::fail(2, &format!("{} with --tag-by-source", flag));
+        }
+    }
+    let file_path = inputs[0].as_path();
+    #[cfg(feature = "duckdb")]
+    let started_at = time::SystemTime::now();
+
+    signals::install();
+
+    let nice = cli
+        .nice
+        .or(cli.thread_priority.map(priority::ThreadPriority::nice));
+    if let Some(nice) = nice {
+        if let Err(err) = priority::set_nice(nice) {
+            log::info!(
+                "Unable to set the worker priority to nice {}: {}",
+                nice,
+                err
+            );
+        }
+    }
 
-    // let cores: usize = std::thread::available_parallelism().unwrap().into();
-    // println!("{}", cores);
+    let mut config = Config {
+        projection: projection(cli, file_path),
+        mode: cli.mode,
+        map: cli.map,
+        overlap: cli.overlap.map(|overlap| overlap as usize),
+        threads: cli.threads.map(|threads| threads as usize),
+        chunk_size: cli.chunk_size,
+        progress: cli.progress,
+        counters: stats_level(cli) == Some(stats::Level::Detailed),
+        sketches: sketch::Wanted {
+            digests: !percentiles(cli).is_empty(),
+            histograms: cli.histogram.is_some(),
+        },
+        malformed: cli
+            .on_malformed
+            .or(cli.strict.then_some(malformed::Action::Abort))
+            .map(|action| malformed::Policy {
+                action,
+                strict: cli.strict,
+                line_ending: cli.line_ending,
+                keep_lines: cli.malformed_log.is_some(),
+            }),
+        ..Config::default()
+    };
+    if config.counters && cfg!(not(all(feature = "perf", target_os = "linux"))) {
+        log::info!("Hardware counters need a Linux build with `--features perf`");
+    }
+    if config.mode == Mode::MapBench {
+        if let Err(err) = mapbench::run(file_path, config.projection) {
+            eprintln!("map-bench failed: {}", err);
+            hooks::fail(1, &err.to_string());
+        }
+        return;
+    }
+    if let Some(strategies) = cli.ab {
+        match ab::run(inputs, &config, strategies, cli.warmup, cli.repeat) {
+            Ok(true) if signals::interrupted() => hooks::fail(130, "interrupted"),
+            Ok(true) => {}
+            Ok(false) => hooks::fail(1, "--ab results differ"),
+            Err(err) => {
+                eprintln!("--ab failed: {}", err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        return;
+    }
 
-    let file_path = "measurements.txt";
+    // Only complete runs produce a result worth caching, and the cache keeps neither digests
+    // nor malformed lines, which a policy may also turn into a failure.
+    let cache = match &cli.cache_dir {
+        Some(dir)
+            if config.mode == Mode::Full
+                && !config.sketches.any()
+                && config.malformed.is_none() =>
+        {
+            dir.clone().or_else(result_cache::default_dir)
+        }
+        _ => None,
+    }
+    .and_then(|dir| match result_cache::Fingerprint::of(file_path) {
+        Ok(fingerprint) => Some((dir, fingerprint)),
+        Err(err) => {
+            log::info!("Unable to fingerprint {}: {}", file_path.display(), err);
+            None
+        }
+    });
 
-    let mut station_temperatures: StationTemperatures = AHashMap::with_capacity(500);
+    let mut samples = Vec::with_capacity(cli.repeat as usize);
+    let mut last_run = None;
+    let mut sources = Vec::new();
+    if let Some((dir, fingerprint)) = cache.as_ref().filter(|_| !cli.no_cache) {
+        let lookup_start = time::Instant::now();
+        match result_cache::lookup(dir, fingerprint, config.projection) {
+            Ok(Some((station_temperatures, total_lines))) => {
+                log::info!("Result served from the cache in {}", dir.display());
+                samples.push(lookup_start.elapsed());
+                last_run = Some(Aggregation {
+                    station_temperatures,
+                    total_lines,
+                    stats: Stats::default(),
+                    resume_offset: None,
+                    malformed: malformed::Report::default(),
+                    sketches: Sketches::default(),
+                });
+            }
+            Ok(None) => {}
+            Err(err) => log::info!("Unable to read the cache in {}: {}", dir.display(), err),
+        }
+    }
+    let from_cache = last_run.is_some();
+
+    if cli.preload && !from_cache && !obrc::is_obrc(file_path) && !is_parquet(file_path) {
+        match preload::load(file_path, cli.preload_limit, &config.limits) {
+            Ok(data) => config.preloaded = Some(Arc::new(data)),
+            Err(err) => {
+                eprintln!("Unable to preload {}: {}", file_path.display(), err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+    }
 
-    // Process first line
-    let mut file = File::open(file_path).expect("Unable to open file");
-    let mut buf = [0; KEY_SIZE + 5];
-    file.seek(SeekFrom::Start(0)).unwrap();
-    file.read(&mut buf).unwrap();
-    let first_line = str::from_utf8(&buf)
-        .unwrap()
-        .split('\n')
-        .collect::<Vec<&str>>()[0];
-    let (key, value) = process_weather_line(first_line);
-    station_temperatures.insert(key, value);
+    if !from_cache {
+        match inputs {
+            [file_path] => config.prepare(file_path, cli.engine),
+            // Mapping, io_uring and the dense index each serve a single file.
+            _ => {
+                if cli.engine != Engine::Read {
+                    log::info!(
+                        "Several inputs are read chunk by chunk, falling back to --engine read"
+                    );
+                }
+                if config.map == MapLayout::Dense {
+                    log::info!(
+                        "--map dense samples a single input, falling back to --map per-worker"
+                    );
+                }
+            }
+        }
+    }
 
-    let total_lines = Arc::new(AtomicU32::new(1));
-    let station_temperatures_list: Arc<Mutex<Vec<StationTemperatures>>> =
-        Arc::new(Mutex::new(Vec::with_capacity(THREAD_COUNT)));
+    for _ in 0..cli.warmup {
+        if from_cache || signals::interrupted() {
+            break;
+        }
+        aggregate_or_fail(inputs, &config, cli.tag_by_source);
+    }
 
-    (0..STAGE_COUNT).for_each(|stage_index| {
-        let mut file_reader_threads = Vec::with_capacity(THREAD_COUNT);
+    // Only the last timed run is reported; the others just contribute their timings.
+    for run in (0..cli.repeat).filter(|_| !from_cache) {
+        for file_path in inputs.iter().filter(|_| cli.drop_caches) {
+            if let Err(err) = cache::drop_cached(file_path) {
+                eprintln!(
+                    "Unable to drop {} from the page cache: {}",
+                    file_path.display(),
+                    err
+                );
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        if run == 0
+            && inputs.len() == 1
+            && !is_stdin(file_path)
+            && (stats_level(cli).is_some() || cli.drop_caches)
+        {
+            match cache::resident_fraction(file_path) {
+                Ok(Some(resident)) => log::info!(
+                    "Page cache: {:.1}% of the input resident before the run",
+                    resident * 100.0
+                ),
+                Ok(None) => {}
+                Err(err) => log::info!("Unable to query the page cache: {}", err),
+            }
+        }
 
-        (0..THREAD_COUNT).for_each(|thread_index| {
-            let mut buf = [0; BUFFER_SIZE + SINGLE_ROW_SIZE];
-            let start = stage_index * BUFFER_SIZE * THREAD_COUNT + thread_index * BUFFER_SIZE;
+        let run_start = time::Instant::now();
+        let (aggregation, run_sources) = aggregate_or_fail(inputs, &config, cli.tag_by_source);
+        samples.push(run_start.elapsed());
+        let interrupted = aggregation.resume_offset.is_some();
+        last_run = Some(aggregation);
+        sources = run_sources;
+        if interrupted {
+            break;
+        }
+    }
+    let Aggregation {
+        mut station_temperatures,
+        total_lines,
+        stats,
+        resume_offset,
+        malformed,
+        sketches,
+    } = last_run.expect("--repeat is at least 1");
+
+    if let Some((dir, fingerprint)) = cache.as_ref().filter(|_| !from_cache) {
+        if resume_offset.is_none() {
+            if let Err(err) = result_cache::store(
+                dir,
+                fingerprint,
+                config.projection,
+                &station_temperatures,
+                total_lines,
+            ) {
+                log::info!("Unable to write the cache in {}: {}", dir.display(), err);
+            }
+        }
+    }
 
-            let station_temperatures_list = Arc::clone(&station_temperatures_list);
-            let total_lines = Arc::clone(&total_lines);
+    let mut station_temperatures = finish(&mut station_temperatures);
+    output::sort(&mut station_temperatures, cli.sort_by, cli.desc);
+    // The DuckDB sink still gets every station, the report only the extremes.
+    let mut report_stations = station_temperatures.clone();
+    output::keep_extremes(&mut report_stations, cli.desc, cli.top, cli.bottom);
+    let mut source_labels = Vec::new();
+    if cli.tag_by_source {
+        // One input after the other, each sorted and cut to its extremes on its own.
+        report_stations.clear();
+        for (file_path, source) in inputs.iter().zip(&mut sources) {
+            let mut stations = finish(source);
+            output::sort(&mut stations, cli.sort_by, cli.desc);
+            output::keep_extremes(&mut stations, cli.desc, cli.top, cli.bottom);
+            let label = file_path.display().to_string();
+            source_labels.extend(std::iter::repeat_n(label, stations.len()));
+            report_stations.extend(stations);
+        }
+    }
+    let end_time = *samples.last().unwrap();
+
+    let wanted_percentiles = percentiles(cli);
+    let options = output::Options {
+        format: cli.format,
+        pg_table: &cli.pg_table,
+        compression: cli.parquet_compression,
+        with_count: cli.with_count,
+        percentiles: config.sketches.digests.then_some(output::Percentiles {
+            wanted: &wanted_percentiles,
+            sketches: &sketches,
+        }),
+        sources: cli.tag_by_source.then_some(&source_labels[..]),
+    };
+    match &cli.output {
+        Some(path) => {
+            if let Err(err) = output::write_report_file(path, &options, &report_stations) {
+                eprintln!("Unable to write {}: {}", path.display(), err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+        None => {
+            let report = output::render(&options, &report_stations);
+            let mut stdout = io::stdout().lock();
+            if let Err(err) = stdout.write_all(&report).and_then(|_| stdout.flush()) {
+                eprintln!("Unable to write the report: {}", err);
+                hooks::fail(1, &err.to_string());
+            }
+        }
+    }
 
-            let mut file = File::open(file_path).expect("Unable to open file");
+    // Diagnostics go to stderr so stdout only carries the report.
+    if let Some(resume_offset) = resume_offset {
+        eprintln!(
+            "PARTIAL RESULTS: interrupted, input processed up to byte {}",
+            resume_offset
+        );
+    }
+    let summary = measure::Summary::new(&samples);
+    if cli.report_best {
+        // Leaderboard style: the best and median wall time of the timed runs.
+        log::info!("Best time: {:?}", summary.min);
+        log::info!(
+            "Median time: {:?} over {} runs",
+            summary.median,
+            summary.runs
+        );
+    } else {
+        log::verbose!("Elapsed time: {:?}", end_time);
+        if samples.len() > 1 && log::enabled(log::Level::Normal) {
+            summary.print();
+        }
+    }
+    // Repeated runs are a benchmark, which states its speed against the naive baseline.
+    if samples.len() > 1 && resume_offset.is_none() && log::enabled(log::Level::Normal) {
+        match measure::Baseline::measure(inputs) {
+            Ok(Some(baseline)) => baseline.print(&summary),
+            Ok(None) => {}
+            Err(err) => log::info!("Unable to time the baseline: {}", err),
+        }
+    }
 
-            let file_reader_thread = thread::spawn(move || {
-                file.seek(SeekFrom::Start(start as u64)).unwrap();
-                file.read(&mut buf).unwrap();
-                let (station_temperatures, lines_count) = process_thread(&buf, SINGLE_ROW_SIZE);
+    if malformed.total() > 0 && log::enabled(log::Level::Normal) {
+        malformed.print();
+    }
+    if let Some(dir) = &cli.histogram {
+        if let Err(err) = sketches.write_histograms(dir) {
+            eprintln!(
+                "Unable to write the histograms to {}: {}",
+                dir.display(),
+                err
+            );
+            hooks::fail(1, &err.to_string());
+        }
+    }
+    if let Some(path) = &cli.malformed_log {
+        if let Err(err) = malformed.write_log(path) {
+            eprintln!("Unable to write {}: {}", path.display(), err);
+            hooks::fail(1, &err.to_string());
+        }
+    }
 
-                total_lines.fetch_add(lines_count, std::sync::atomic::Ordering::SeqCst);
+    let paused = signals::paused_duration();
+    if !paused.is_zero() {
+        log::info!("Paused time: {:?}", paused);
+    }
 
-                let mut station_temperatures_list = station_temperatures_list.lock().unwrap();
-                station_temperatures_list.push(station_temperatures);
-            });
+    if stats_level(cli).is_some() && !from_cache && log::enabled(log::Level::Normal) {
+        stats.print(end_time.saturating_sub(paused), total_lines);
+    }
+    if (cli.summary && log::enabled(log::Level::Normal)) || log::enabled(log::Level::Verbose) {
+        // A cached result read no bytes, so it gets no throughput.
+        output::Summary::of(&station_temperatures)
+            .print(stats.bytes_read, end_time.saturating_sub(paused));
+    }
+    if cfg!(feature = "heap-profile") && log::enabled(log::Level::Normal) {
+        heap::print();
+    }
 
-            file_reader_threads.push(file_reader_thread);
-        });
+    #[cfg(feature = "duckdb")]
+    if let Some(database) = &cli.output_duckdb {
+        let run = duckdb_sink::Run {
+            started_at,
+            input: &run_labels(cli).0,
+            elapsed: end_time,
+            total_lines,
+        };
+        if let Err(err) = duckdb_sink::append(database, &cli.table, &run, &station_temperatures) {
+            eprintln!("Unable to write to {}: {}", database.display(), err);
+            hooks::fail(1, &err.to_string());
+        }
+    }
 
-        file_reader_threads
-            .into_iter()
-            .for_each(|thread| thread.join().unwrap());
+    if let Some(resume_offset) = resume_offset {
+        hooks::fail(
+            130,
+            &format!("interrupted, resume offset {}", resume_offset),
+        );
+    }
+}
 
-        println!("Stage: {:?} completed", stage_index);
+/// The worker breakdown `--stats` asked for, the most detailed of the levels it names.
+fn stats_level(cli: &RunArgs) -> Option<stats::Level> {
+    let levels = cli.stats.iter().filter_map(|request| match request {
+        stats::Request::Level(level) => Some(*level),
+        stats::Request::Percentile(_) => None,
     });
+    levels.max_by_key(|level| *level == stats::Level::Detailed)
+}
 
-    let station_temperatures_list = station_temperatures_list.lock().unwrap();
-    station_temperatures_list.iter().for_each(|st| {
-        st.iter().for_each(|(station_name, data)| {
-            if let Some(parent_data) = station_temperatures.get_mut(station_name) {
-                parent_data.merge(&data);
-            } else {
-                station_temperatures.insert(*station_name, *data);
-            }
-        });
-    });
+/// The percentiles `--stats` asked for, in the order it names them.
+fn percentiles(cli: &RunArgs) -> Vec<Percentile> {
+    cli.stats
+        .iter()
+        .filter_map(|request| match request {
+            stats::Request::Percentile(percentile) => Some(*percentile),
+            stats::Request::Level(_) => None,
+        })
+        .collect()
+}
 
-    station_temperatures.values_mut().for_each(|data| {
-        data.update_mean();
-        data.round();
-    });
+/// Exits when `file_path` cannot be aggregated with the flags in `cli`.
+fn check_input(cli: &RunArgs, file_path: &Path) {
+    if is_stdin(file_path) {
+        // A stream can be read once, in order, and has nothing to put in the page cache.
+        let file_only = [
+            ("--repeat", cli.repeat > 1),
+            ("--warmup", cli.warmup > 0),
+            ("--ab", cli.ab.is_some()),
+            ("--preload", cli.preload),
+            ("--drop-caches", cli.drop_caches),
+            ("--cache-dir", cli.cache_dir.is_some()),
+            ("--mode map-bench", cli.mode == Mode::MapBench),
+        ];
+        if let Some((flag, _)) = file_only.iter().find(|(_, set)| *set) {
+            eprintln!("{} needs an input file, not stdin", flag);
+            hooks::fail(2, &format!("{} with stdin input", flag));
+        }
+    } else if is_remote(file_path) {
+        if cfg!(not(feature = "remote")) {
+            eprintln!(
+                "{} needs a build with `--features remote`",
+                file_path.display()
+            );
+            hooks::fail(2, "remote input without the remote feature");
+        }
+        // Downloaded into the workers' buffers, so nothing lands on disk or in the page cache.
+        let local_only = [
+            ("--preload", cli.preload),
+            ("--drop-caches", cli.drop_caches),
+            ("--cache-dir", cli.cache_dir.is_some()),
+            ("--mode map-bench", cli.mode == Mode::MapBench),
+        ];
+        if let Some((flag, _)) = local_only.iter().find(|(_, set)| *set) {
+            eprintln!("{} needs a local input", flag);
+            hooks::fail(2, &format!("{} with remote input", flag));
+        }
+    } else if let Err(err) = File::open(file_path).and_then(|file| {
+        if file.metadata()?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "is a directory",
+            ));
+        }
+        Ok(())
+    }) {
+        eprintln!("Unable to read {}: {}", file_path.display(), err);
+        hooks::fail(1, &err.to_string());
+    }
+    if is_parquet(file_path) {
+        if cfg!(not(feature = "parquet")) {
+            eprintln!(
+                "{} needs a build with `--features parquet`",
+                file_path.display()
+            );
+            hooks::fail(2, "Parquet input without the parquet feature");
+        }
+        if cli.mode == Mode::MapBench {
+            eprintln!("--mode map-bench needs a text input");
+            hooks::fail(2, "--mode map-bench with Parquet input");
+        }
+    }
+    if compressed::Format::detect(file_path) != compressed::Format::Plain {
+        // Decompressed on the fly, so there is no text on disk to load or map.
+        let plain_only = [
+            ("--preload", cli.preload),
+            ("--mode map-bench", cli.mode == Mode::MapBench),
+        ];
+        if let Some((flag, _)) = plain_only.iter().find(|(_, set)| *set) {
+            eprintln!("{} needs an uncompressed input", flag);
+            hooks::fail(2, &format!("{} with compressed input", flag));
+        }
+    }
+}
 
-    let end_time = start_time.elapsed();
+/// The columns `--station-col` and `--value-col` pick, with names looked up in the header of
+/// `file_path`. Exits when a name is not in it.
+fn projection(cli: &RunArgs, file_path: &Path) -> Option<Projection> {
+    let (station, value) = cli.station_col.as_ref().zip(cli.value_col.as_ref())?;
+    let names = match (station, value) {
+        (Column::Index(_), Column::Index(_)) => Vec::new(),
+        _ => column_names(file_path, cli.input_format).unwrap_or_else(|err| {
+            eprintln!(
+                "Unable to read the column names of {}: {}",
+                file_path.display(),
+                err
+            );
+            hooks::fail(1, &err.to_string())
+        }),
+    };
+    let resolve = |column: &Column| {
+        column.resolve(&names).unwrap_or_else(|| {
+            eprintln!("{} has no column named `{}`", file_path.display(), column);
+            hooks::fail(2, "column not in the header")
+        })
+    };
+    Some(Projection {
+        station_column: resolve(station),
+        value_column: resolve(value),
+        format: cli.input_format,
+    })
+}
 
-    let mut station_temperatures: Vec<_> = station_temperatures.iter().collect();
-    station_temperatures.sort_by(|a, b| a.0.cmp(b.0));
+/// The columns of a Parquet file, or those the first line of a text file names, decompressed.
+/// A stream or a download would have to be read twice.
+fn column_names(file_path: &Path, format: InputFormat) -> io::Result<Vec<Vec<u8>>> {
+    if is_stdin(file_path) || is_remote(file_path) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "column names need a local file, give zero-based indices instead",
+        ));
+    }
+    #[cfg(feature = "parquet")]
+    if is_parquet(file_path) {
+        let columns = onebrc::parquet_input::columns(file_path)?;
+        return Ok(columns.into_iter().map(String::into_bytes).collect());
+    }
+    let compression = compressed::Format::detect(file_path);
+    let mut header = Vec::new();
+    io::BufReader::new(compressed::decoder(file_path, compression)?)
+        .take(WIDE_ROW_SIZE as u64)
+        .read_until(b'\n', &mut header)?;
+    Ok(projection::header_names(&header, format))
+}
 
-    for (station_name, data) in station_temperatures.iter() {
-        println!(
-            "Station: {:?}, Min: {}, Mean: {}, Max: {}",
-            str::from_utf8(station_name.as_slice())
-                .unwrap()
-                .replace("\0", ""),
-            data.min_temperature,
-            data.mean_temperature,
-            data.max_temperature
-        );
-        // println!(
-        //     "{}={}/{}/{}",
-        //     station_name, data.min_temperature, data.mean_temperature, data.max_temperature
-        // );
-    }
-
-    println!(
-        "Total lines: {:?}",
-        total_lines.load(std::sync::atomic::Ordering::SeqCst)
-    );
-    println!("Total stations: {:?}", station_temperatures.len());
-    println!("Elapsed time: {:?}", end_time);
+/// `aggregate_files`, or `aggregate_sources` for `--tag-by-source`, exiting through the
+/// `--on-error` hook when an input cannot be read. The station totals of the inputs are only
+/// kept apart for `--tag-by-source`.
+fn aggregate_or_fail(
+    file_paths: &[PathBuf],
+    config: &Config,
+    tag_by_source: bool,
+) -> (Aggregation, Vec<StationTemperatures>) {
+    let result = match tag_by_source {
+        true => aggregate_sources(file_paths, config),
+        false => aggregate_files(file_paths, config).map(|aggregation| (aggregation, Vec::new())),
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("run failed: {}", err);
+        hooks::fail(1, &err.to_string())
+    })
 }
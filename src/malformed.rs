@@ -0,0 +1,254 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str;
+
+use ahash::AHashMap;
+
+use crate::{add_row, key_prefix, StationTemperatures, Tenths, KEY_SIZE};
+
+/// What `--on-malformed` does with a line that is not `<station>;<temperature>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Action {
+    /// Leave the line out of the report and count it
+    Skip,
+    /// Stop the run at the first such line
+    Abort,
+}
+
+/// What `--line-ending` takes for the end of a line when lines are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LineEnding {
+    /// `\n` or `\r\n`, line by line, so files written on Windows check clean
+    Auto,
+    /// Only `\n`, as the 1BRC input contract has it; a `\r` before it is part of the line
+    Lf,
+}
+
+impl LineEnding {
+    /// `line` without the `\r` that ends it on CRLF input, when that counts as the line end.
+    #[doc(hidden)]
+    pub fn trim(self, line: &[u8]) -> &[u8] {
+        match self {
+            LineEnding::Auto => line.strip_suffix(b"\r").unwrap_or(line),
+            LineEnding::Lf => line,
+        }
+    }
+}
+
+/// How the checking parser treats malformed lines.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub action: Action,
+    /// Also enforce the rest of the 1BRC input contract, for `--strict`.
+    pub strict: bool,
+    pub line_ending: LineEnding,
+    /// Keep every skipped line for `--malformed-log`.
+    pub keep_lines: bool,
+}
+
+/// Why a line was rejected, in the order the checks run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    BadUtf8,
+    MissingSeparator,
+    /// Only checked with `--strict`.
+    NameLength,
+    /// Only checked with `--strict`.
+    ExtraSeparator,
+    /// Only checked with `--strict`.
+    BadFormat,
+    BadNumber,
+    OutOfRange,
+}
+
+const CATEGORIES: [Category; 7] = [
+    Category::BadUtf8,
+    Category::MissingSeparator,
+    Category::NameLength,
+    Category::ExtraSeparator,
+    Category::BadFormat,
+    Category::BadNumber,
+    Category::OutOfRange,
+];
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Category::BadUtf8 => "bad UTF-8",
+            Category::MissingSeparator => "missing `;`",
+            Category::NameLength => "name not 1 to 100 bytes",
+            Category::ExtraSeparator => "more than one `;`",
+            Category::BadFormat => "temperature not like -12.3",
+            Category::BadNumber => "not a number",
+            Category::OutOfRange => "out of range",
+        })
+    }
+}
+
+/// A rejected line and the input offset it starts at.
+#[derive(Debug, Clone)]
+#[doc(hidden)]
+pub struct Line {
+    pub offset: u64,
+    pub category: Category,
+    pub text: Vec<u8>,
+}
+
+/// What the checking parser rejected during one run, or in the chunks of one of its workers.
+#[derive(Debug, Default)]
+#[doc(hidden)]
+pub struct Report {
+    counts: [u64; CATEGORIES.len()],
+    /// The kept lines, in input order once `sort` ran.
+    pub lines: Vec<Line>,
+    aborted: bool,
+}
+
+impl Report {
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Lines rejected for `category`.
+    pub fn count(&self, category: Category) -> u64 {
+        self.counts[category as usize]
+    }
+
+    /// Whether a line was rejected under `--on-malformed abort`. The schedulers stop handing
+    /// out chunks once a worker's report says so, and the chunks in flight still finish, so the
+    /// earliest kept line is the first malformed line of the input.
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// Puts the kept lines in input order, after merging the reports of several workers.
+    pub fn sort(&mut self) {
+        self.lines.sort_unstable_by_key(|line| line.offset);
+    }
+
+    /// Adds the lines `other` rejected in an input that starts `offset` bytes into the run.
+    pub fn merge(&mut self, other: Report, offset: u64) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        self.aborted |= other.aborted;
+        self.lines.extend(other.lines.into_iter().map(|line| Line {
+            offset: line.offset + offset,
+            ..line
+        }));
+    }
+
+    pub fn print(&self) {
+        let categories = CATEGORIES
+            .iter()
+            .zip(self.counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(category, count)| format!("{}: {}", category, count))
+            .collect::<Vec<_>>();
+        eprintln!(
+            "Skipped {} malformed lines ({})",
+            self.total(),
+            categories.join(", ")
+        );
+    }
+
+    /// Writes one `<offset>\t<category>\t<line>` row per kept line, the line as it was read.
+    pub fn write_log(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for line in &self.lines {
+            write!(out, "{}\t{}\t", line.offset, line.category)?;
+            out.write_all(&line.text)?;
+            out.write_all(b"\n")?;
+        }
+        out.into_inner()?.sync_all()
+    }
+}
+
+/// The station and temperature in tenths of a valid line.
+#[doc(hidden)]
+pub fn check(line: &[u8], strict: bool) -> Result<(&[u8], i32), Category> {
+    let line = str::from_utf8(line).map_err(|_| Category::BadUtf8)?;
+    let (station, temperature) = line.split_once(';').ok_or(Category::MissingSeparator)?;
+    if strict {
+        if !(1..=KEY_SIZE).contains(&station.len()) {
+            return Err(Category::NameLength);
+        }
+        if temperature.contains(';') {
+            return Err(Category::ExtraSeparator);
+        }
+        if !is_contract_number(temperature.as_bytes()) {
+            return Err(Category::BadFormat);
+        }
+    }
+    let Tenths(tenths) = Tenths::parse(temperature).ok_or(Category::BadNumber)?;
+    if !(-999..=999).contains(&tenths) {
+        return Err(Category::OutOfRange);
+    }
+    Ok((station.as_bytes(), tenths as i32))
+}
+
+/// An optional `-`, one or two digits, `.` and exactly one digit.
+fn is_contract_number(temperature: &[u8]) -> bool {
+    let digits = temperature.strip_prefix(b"-").unwrap_or(temperature);
+    match digits {
+        [whole @ .., b'.', tenth] => {
+            (1..=2).contains(&whole.len())
+                && whole.iter().all(u8::is_ascii_digit)
+                && tenth.is_ascii_digit()
+        }
+        _ => false,
+    }
+}
+
+/// Counterpart of `process_buffer` that checks every line, for `--on-malformed`, and adds the
+/// rejected ones to `report`. `offset` is where `buf` starts in the input, to locate them.
+/// `sketch` sees every valid row, its station cut to what a `Key` keeps.
+#[doc(hidden)]
+pub fn process_buffer(
+    buf: &[u8],
+    offset: u64,
+    policy: Policy,
+    report: &mut Report,
+    mut sketch: impl FnMut(&[u8], i32),
+) -> (StationTemperatures, u32) {
+    let mut station_temperatures: StationTemperatures = AHashMap::with_capacity(1000);
+    let mut lines_count = 0;
+
+    let mut line_start = 0;
+    for line in buf.split(|&b| b == b'\n') {
+        let line_offset = offset + line_start as u64;
+        line_start += line.len() + 1;
+        if line.is_empty() && line_start > buf.len() {
+            // What follows the last newline.
+            break;
+        }
+
+        match check(policy.line_ending.trim(line), policy.strict) {
+            Ok((station, temperature)) => {
+                add_row(&mut station_temperatures, station, temperature);
+                sketch(key_prefix(station), temperature);
+                lines_count += 1;
+            }
+            Err(category) => {
+                report.counts[category as usize] += 1;
+                if policy.keep_lines || policy.action == Action::Abort {
+                    report.lines.push(Line {
+                        offset: line_offset,
+                        category,
+                        text: line.to_vec(),
+                    });
+                }
+                if policy.action == Action::Abort {
+                    report.aborted = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    (station_temperatures, lines_count)
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::thread;
+
+use napi::bindgen_prelude::Object;
+use napi::{Env, Error, Result, Status};
+use napi_derive::napi;
+
+use crate::{Aggregator, Engine, MapLayout};
+
+/// The options of `processFile`, all optional. `engine` and `map` take the values of the
+/// binary's `--engine` and `--map`.
+#[napi(object)]
+#[derive(Default)]
+pub struct ProcessOptions {
+    pub threads: Option<u32>,
+    pub chunk_size: Option<u32>,
+    pub engine: Option<String>,
+    pub map: Option<String>,
+}
+
+/// One station of the object `processFile` resolves to, in degrees rounded to a tenth.
+#[napi(object)]
+pub struct Station {
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub count: u32,
+}
+
+/// Aggregates the measurements at `path` and resolves to `{station: {min, mean, max, count}}`,
+/// sorted by station. The run gets a thread of its own besides its workers, so neither the
+/// event loop nor the libuv pool waits on it.
+#[napi(ts_return_type = "Promise<Record<string, Station>>")]
+pub fn process_file(
+    env: &Env,
+    path: String,
+    options: Option<ProcessOptions>,
+) -> Result<Object<'_>> {
+    let aggregator = aggregator(options.unwrap_or_default())?;
+    let (deferred, promise) = env.create_deferred()?;
+    thread::spawn(
+        move || match aggregator.process_file(&PathBuf::from(path)) {
+            Ok(report) => deferred.resolve(move |_| {
+                Ok(report
+                    .iter()
+                    .map(|station| {
+                        let stats = Station {
+                            min: station.min,
+                            mean: station.mean,
+                            max: station.max,
+                            count: station.count,
+                        };
+                        (station.name.to_string(), stats)
+                    })
+                    .collect::<BTreeMap<_, _>>())
+            }),
+            Err(err) => deferred.reject(Error::from_reason(err.to_string())),
+        },
+    );
+    Ok(promise)
+}
+
+fn aggregator(options: ProcessOptions) -> Result<Aggregator> {
+    let mut builder = Aggregator::builder();
+    if let Some(threads) = options.threads {
+        builder = builder.threads(threads as usize);
+    }
+    if let Some(chunk_size) = options.chunk_size {
+        builder = builder.chunk_size(chunk_size as usize);
+    }
+    if let Some(engine) = options.engine {
+        builder = builder.engine(match engine.as_str() {
+            "read" => Engine::Read,
+            "mmap" => Engine::Mmap,
+            "uring" => Engine::Uring,
+            _ => return Err(invalid("engine", &engine)),
+        });
+    }
+    if let Some(map) = options.map {
+        builder = builder.map(match map.as_str() {
+            "per-worker" => MapLayout::PerWorker,
+            "sharded" => MapLayout::Sharded,
+            "dense" => MapLayout::Dense,
+            _ => return Err(invalid("map", &map)),
+        });
+    }
+    builder
+        .build()
+        .map_err(|err| Error::new(Status::InvalidArg, err.to_string()))
+}
+
+fn invalid(option: &str, value: &str) -> Error {
+    Error::new(
+        Status::InvalidArg,
+        format!("invalid {}: {:?}", option, value),
+    )
+}
@@ -0,0 +1,553 @@
+/*
+* `.obrc` is a compact binary layout of a measurements file:
+    magic "OBRC", version u32
+    blocks: rows u32, compressed size u32, lz4(station ids [u16; rows] ++ tenths [i16; rows])
+    dictionary: station count u32, then per station a name length u8 and the name bytes
+    block index: block count u32, then per block its offset u64 and rows u32
+    footer: dictionary offset u64, total rows u64, magic "OBRC"
+* All integers are little endian. Station ids index into the dictionary.
+*/
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+
+use crate::sketch::Sketches;
+use crate::stats::Stats;
+use crate::{malformed, signals, Aggregation, Key, StationTemperatures, WeatherData};
+
+const MAGIC: &[u8; 4] = b"OBRC";
+const VERSION: u32 = 1;
+const FOOTER_SIZE: u64 = 20;
+const BLOCK_ROWS: usize = 1 << 16;
+const MAX_STATIONS: usize = u16::MAX as usize + 1;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct ConvertArgs {
+    /// Measurements text file to convert
+    pub input: PathBuf,
+
+    /// Path of the `.obrc` file to write
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub out: PathBuf,
+
+    /// Whether a `\r` before the newline ends the line too or is part of it
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_enum, default_value_t = malformed::LineEnding::Auto)
+    )]
+    pub line_ending: malformed::LineEnding,
+}
+
+pub fn convert(args: &ConvertArgs) -> io::Result<()> {
+    let result = write_obrc(&args.input, &args.out, args.line_ending);
+    if result.is_err() {
+        let _ = fs::remove_file(&args.out);
+    }
+    result
+}
+
+fn write_obrc(input: &Path, output: &Path, line_ending: malformed::LineEnding) -> io::Result<()> {
+    let mut reader = BufReader::with_capacity(1 << 20, File::open(input)?);
+    let mut writer = BufWriter::with_capacity(1 << 20, File::create(output)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+
+    let mut offset = (MAGIC.len() + 4) as u64;
+    let mut ids: AHashMap<Vec<u8>, u16> = AHashMap::with_capacity(10_000);
+    let mut names: Vec<Vec<u8>> = Vec::with_capacity(10_000);
+    let mut block_index: Vec<(u64, u32)> = Vec::new();
+    let mut block_ids: Vec<u16> = Vec::with_capacity(BLOCK_ROWS);
+    let mut block_tenths: Vec<i16> = Vec::with_capacity(BLOCK_ROWS);
+    let mut total_rows = 0u64;
+    let mut line = Vec::with_capacity(128);
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let record = line_ending.trim(line.strip_suffix(b"\n").unwrap_or(&line));
+        if record.is_empty() {
+            continue;
+        }
+
+        let (name, tenths) = parse_record(record).ok_or_else(|| {
+            invalid_data(format!(
+                "malformed line {}: {:?}",
+                total_rows + 1,
+                String::from_utf8_lossy(record)
+            ))
+        })?;
+
+        let id = match ids.get(name) {
+            Some(&id) => id,
+            None => {
+                if names.len() == MAX_STATIONS {
+                    return Err(invalid_data(format!(
+                        "more than {} distinct stations",
+                        MAX_STATIONS
+                    )));
+                }
+                let id = names.len() as u16;
+                ids.insert(name.to_vec(), id);
+                names.push(name.to_vec());
+                id
+            }
+        };
+
+        block_ids.push(id);
+        block_tenths.push(tenths);
+        total_rows += 1;
+
+        if block_ids.len() == BLOCK_ROWS {
+            block_index.push((offset, block_ids.len() as u32));
+            offset += write_block(&mut writer, &block_ids, &block_tenths)?;
+            block_ids.clear();
+            block_tenths.clear();
+        }
+    }
+
+    if !block_ids.is_empty() {
+        block_index.push((offset, block_ids.len() as u32));
+        offset += write_block(&mut writer, &block_ids, &block_tenths)?;
+    }
+
+    let dictionary_offset = offset;
+    writer.write_all(&(names.len() as u32).to_le_bytes())?;
+    for name in &names {
+        writer.write_all(&[name.len() as u8])?;
+        writer.write_all(name)?;
+    }
+
+    writer.write_all(&(block_index.len() as u32).to_le_bytes())?;
+    for (block_offset, rows) in &block_index {
+        writer.write_all(&block_offset.to_le_bytes())?;
+        writer.write_all(&rows.to_le_bytes())?;
+    }
+
+    writer.write_all(&dictionary_offset.to_le_bytes())?;
+    writer.write_all(&total_rows.to_le_bytes())?;
+    writer.write_all(MAGIC)?;
+    writer.flush()
+}
+
+fn write_block(writer: &mut impl Write, ids: &[u16], tenths: &[i16]) -> io::Result<u64> {
+    let mut raw = Vec::with_capacity(ids.len() * 4);
+    ids.iter()
+        .for_each(|id| raw.extend_from_slice(&id.to_le_bytes()));
+    tenths
+        .iter()
+        .for_each(|t| raw.extend_from_slice(&t.to_le_bytes()));
+
+    let compressed = lz4_flex::block::compress(&raw);
+    writer.write_all(&(ids.len() as u32).to_le_bytes())?;
+    writer.write_all(&(compressed.len() as u32).to_le_bytes())?;
+    writer.write_all(&compressed)?;
+    Ok(8 + compressed.len() as u64)
+}
+
+/// Splits `<station>;<temperature>` into the name and the temperature in tenths of a degree.
+fn parse_record(record: &[u8]) -> Option<(&[u8], i16)> {
+    let separator = record.iter().rposition(|&b| b == b';')?;
+    let (name, temperature) = (&record[..separator], &record[separator + 1..]);
+    if name.is_empty() || name.len() > 100 {
+        return None;
+    }
+
+    let (negative, digits) = match temperature.strip_prefix(b"-") {
+        Some(digits) => (true, digits),
+        None => (false, temperature),
+    };
+    let tenths = match digits {
+        [a, b'.', b] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            (a - b'0') as i16 * 10 + (b - b'0') as i16
+        }
+        [a, b, b'.', c] if a.is_ascii_digit() && b.is_ascii_digit() && c.is_ascii_digit() => {
+            (a - b'0') as i16 * 100 + (b - b'0') as i16 * 10 + (c - b'0') as i16
+        }
+        _ => return None,
+    };
+
+    Some((name, if negative { -tenths } else { tenths }))
+}
+
+/// Returns true when the file starts with the `.obrc` magic bytes.
+pub fn is_obrc(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map(|_| &magic == MAGIC)
+        .unwrap_or(false)
+}
+
+/// Station dictionary and block index of a `.obrc` file.
+pub struct ObrcFile {
+    names: Vec<Vec<u8>>,
+    blocks: Vec<(u64, u32)>,
+    total_rows: u64,
+}
+
+impl ObrcFile {
+    pub fn open(path: &Path) -> io::Result<ObrcFile> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < MAGIC.len() as u64 + 4 + FOOTER_SIZE {
+            return Err(invalid_data("file is too small".to_string()));
+        }
+
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || read_u32(&header[4..]) != VERSION {
+            return Err(invalid_data("unsupported file version".to_string()));
+        }
+
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        file.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        file.read_exact(&mut footer)?;
+        if &footer[16..] != MAGIC {
+            return Err(invalid_data("missing footer".to_string()));
+        }
+        let dictionary_offset = read_u64(&footer[..8]);
+        let total_rows = read_u64(&footer[8..16]);
+        if dictionary_offset > len - FOOTER_SIZE {
+            return Err(invalid_data("corrupt footer".to_string()));
+        }
+
+        let mut trailer = vec![0u8; (len - FOOTER_SIZE - dictionary_offset) as usize];
+        file.seek(SeekFrom::Start(dictionary_offset))?;
+        file.read_exact(&mut trailer)?;
+        let mut cursor = Cursor(&trailer);
+
+        let station_count = cursor.u32()? as usize;
+        let names = (0..station_count)
+            .map(|_| {
+                let len = cursor.bytes(1)?[0] as usize;
+                Ok(cursor.bytes(len)?.to_vec())
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let block_count = cursor.u32()? as usize;
+        let blocks = (0..block_count)
+            .map(|_| Ok((cursor.u64()?, cursor.u32()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(ObrcFile {
+            names,
+            blocks,
+            total_rows,
+        })
+    }
+}
+
+/// Per-station running totals in tenths of a degree.
+#[derive(Debug, Clone, Copy)]
+struct Totals {
+    min: i16,
+    max: i16,
+    sum: i64,
+    count: u32,
+}
+
+impl Totals {
+    const EMPTY: Totals = Totals {
+        min: i16::MAX,
+        max: i16::MIN,
+        sum: 0,
+        count: 0,
+    };
+
+    #[inline(always)]
+    fn add(&mut self, tenths: i16) {
+        self.min = self.min.min(tenths);
+        self.max = self.max.max(tenths);
+        self.sum += tenths as i64;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Totals) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+/// Reads blocks through its own file handle, reusing the compressed and raw buffers.
+struct BlockReader {
+    file: File,
+    compressed: Vec<u8>,
+    raw: Vec<u8>,
+    bytes_read: u64,
+}
+
+impl BlockReader {
+    fn open(path: &Path) -> io::Result<BlockReader> {
+        Ok(BlockReader {
+            file: File::open(path)?,
+            compressed: Vec::new(),
+            raw: vec![0; BLOCK_ROWS * 4],
+            bytes_read: 0,
+        })
+    }
+
+    fn read(&mut self, index: usize, (offset, rows): (u64, u32)) -> io::Result<()> {
+        let mut header = [0u8; 8];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut header)?;
+        if read_u32(&header[..4]) != rows || rows as usize > BLOCK_ROWS {
+            return Err(invalid_data(format!("corrupt block {}", index)));
+        }
+
+        self.compressed.resize(read_u32(&header[4..]) as usize, 0);
+        self.file.read_exact(&mut self.compressed)?;
+        self.bytes_read += 8 + self.compressed.len() as u64;
+        Ok(())
+    }
+
+    /// Decompresses the last block read, returning its station id and tenths columns as raw bytes.
+    fn decode(&mut self, index: usize, rows: u32) -> io::Result<(&[u8], &[u8])> {
+        let rows = rows as usize;
+        let len = lz4_flex::block::decompress_into(&self.compressed, &mut self.raw)
+            .map_err(|err| invalid_data(format!("block {}: {}", index, err)))?;
+        if len != rows * 4 {
+            return Err(invalid_data(format!("corrupt block {}", index)));
+        }
+        Ok(self.raw[..len].split_at(rows * 2))
+    }
+}
+
+/// Aggregates a `.obrc` file into the same per-station table the text path produces.
+///
+/// Workers claim blocks from a shared counter and fold the id and tenths columns straight
+/// into a dense per-station table, so there is no tokenization or hashing involved.
+pub fn aggregate(path: &Path, workers: usize) -> io::Result<Aggregation> {
+    let obrc = ObrcFile::open(path)?;
+    let workers = workers.min(obrc.blocks.len().max(1));
+    let next_block = AtomicUsize::new(0);
+
+    let results = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| scope.spawn(|| aggregate_blocks(path, &obrc, &next_block)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("a worker thread panicked")))
+            })
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    let mut totals = vec![Totals::EMPTY; obrc.names.len()];
+    let mut phases = [Duration::ZERO; 3];
+    let mut bytes_read = 0;
+    for (worker_totals, worker_phases, worker_bytes) in &results {
+        totals
+            .iter_mut()
+            .zip(worker_totals.iter())
+            .for_each(|(total, worker_total)| total.merge(worker_total));
+        phases
+            .iter_mut()
+            .zip(worker_phases.iter())
+            .for_each(|(phase, worker_phase)| *phase += *worker_phase);
+        bytes_read += worker_bytes;
+    }
+
+    let mut station_temperatures: StationTemperatures = AHashMap::with_capacity(obrc.names.len());
+    for (name, total) in obrc.names.iter().zip(totals.iter()) {
+        if total.count == 0 {
+            continue;
+        }
+
+        let key = Key::new(name);
+        let data = WeatherData {
+            total_temperature: total.sum,
+            min_temperature: total.min as i32,
+            max_temperature: total.max as i32,
+            count: total.count,
+            mean_temperature: 0,
+        };
+
+        station_temperatures
+            .entry(key)
+            .and_modify(|existing| existing.merge(&data))
+            .or_insert(data);
+    }
+
+    let stats = Stats {
+        workers,
+        bytes_read,
+        phases: vec![
+            ("read", phases[0]),
+            ("decode", phases[1]),
+            ("aggregate", phases[2]),
+        ],
+        counters: Vec::new(),
+    };
+
+    // Blocks are claimed in order and claimed blocks always finish, so the rest starts here.
+    let resume_offset = obrc
+        .blocks
+        .get(next_block.load(Ordering::Relaxed))
+        .map(|&(offset, _)| offset);
+    let total_lines = match resume_offset {
+        Some(_) => totals.iter().map(|total| total.count as u64).sum(),
+        None => obrc.total_rows,
+    };
+
+    Ok(Aggregation {
+        station_temperatures,
+        total_lines,
+        stats,
+        resume_offset,
+        malformed: malformed::Report::default(),
+        sketches: Sketches::default(),
+    })
+}
+
+type WorkerResult = (Vec<Totals>, [Duration; 3], u64);
+
+fn aggregate_blocks(
+    path: &Path,
+    obrc: &ObrcFile,
+    next_block: &AtomicUsize,
+) -> io::Result<WorkerResult> {
+    let mut reader = BlockReader::open(path)?;
+    let mut totals = vec![Totals::EMPTY; obrc.names.len()];
+    let mut phases = [Duration::ZERO; 3];
+
+    loop {
+        signals::wait_while_paused();
+        if signals::interrupted() {
+            break;
+        }
+
+        let index = next_block.fetch_add(1, Ordering::Relaxed);
+        let Some(&(offset, rows)) = obrc.blocks.get(index) else {
+            break;
+        };
+
+        let started = Instant::now();
+        reader.read(index, (offset, rows))?;
+        let read = Instant::now();
+        let (ids, tenths) = reader.decode(index, rows)?;
+        let decoded = Instant::now();
+
+        for (id, t) in ids.chunks_exact(2).zip(tenths.chunks_exact(2)) {
+            let id = u16::from_le_bytes([id[0], id[1]]);
+            totals
+                .get_mut(id as usize)
+                .ok_or_else(|| invalid_data(format!("unknown station id {}", id)))?
+                .add(i16::from_le_bytes([t[0], t[1]]));
+        }
+
+        phases[0] += read - started;
+        phases[1] += decoded - read;
+        phases[2] += decoded.elapsed();
+    }
+
+    Ok((totals, phases, reader.bytes_read))
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid_data("truncated dictionary".to_string()));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.bytes(4).map(read_u32)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.bytes(8).map(read_u64)
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregate_file, finish, station_name, Config};
+
+    #[test]
+    fn obrc_conversion_aggregates_like_its_text() {
+        let dir = std::env::temp_dir().join(format!("onebrc-obrc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (text, obrc) = (dir.join("m.txt"), dir.join("m.obrc"));
+        // More than one block of rows, with a CRLF line and a name of the longest kind.
+        let long = "é".repeat(50);
+        let rows = (0..150_000)
+            .map(|i| match i % 4 {
+                0 => format!("Hamburg;{}.{}\n", i % 100 - 50, i % 10),
+                1 => format!("{};-{}.{}\r\n", long, i % 100, i % 7),
+                2 => "Bulawayo;8.9\n".to_string(),
+                _ => format!("St. John's;{}.0\n", i % 60),
+            })
+            .collect::<String>();
+        std::fs::write(&text, &rows).unwrap();
+        convert(&ConvertArgs {
+            input: text.clone(),
+            out: obrc.clone(),
+            line_ending: malformed::LineEnding::Auto,
+        })
+        .unwrap();
+        assert!(is_obrc(&obrc));
+
+        let [from_text, from_obrc] = [&text, &obrc].map(|path| {
+            let mut aggregation = aggregate_file(path, &Config::default()).unwrap();
+            let stations = finish(&mut aggregation.station_temperatures)
+                .into_iter()
+                .map(|(key, data)| {
+                    (
+                        station_name(key),
+                        data.min_temperature,
+                        data.max_temperature,
+                        data.total_temperature,
+                        data.count,
+                    )
+                })
+                .collect::<Vec<_>>();
+            (aggregation.total_lines, stations)
+        });
+        assert_eq!(from_text.0, 150_000);
+        assert_eq!(from_obrc, from_text);
+
+        // A malformed line fails the conversion and leaves no output behind.
+        std::fs::write(&text, format!("{}Hamburg;hot\n", rows)).unwrap();
+        let err = convert(&ConvertArgs {
+            input: text.clone(),
+            out: obrc.clone(),
+            line_ending: malformed::LineEnding::Auto,
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("line 150001"), "{}", err);
+        assert!(!obrc.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
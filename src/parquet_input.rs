@@ -0,0 +1,290 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::Float64Type;
+use arrow_array::RecordBatch;
+use arrow_schema::{DataType, Schema};
+use parquet::arrow::arrow_reader::{ArrowReaderMetadata, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ProjectionMask;
+
+use crate::error::{Error, Result};
+use crate::projection::Projection;
+use crate::sketch::{self, Sketches};
+use crate::stats::Stats;
+use crate::table::StationTable;
+use crate::{
+    heap, key_prefix, log, malformed, merge_tree, signals, Aggregation, Config, StationTemperatures,
+};
+
+/// Rows decoded per batch; the reader's default of 1024 spends more on the batches than on
+/// the rows.
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// Column names picked without `--station-col`, before falling back to the first text column.
+const STATION_NAMES: [&str; 4] = ["station", "station_name", "city", "name"];
+/// Column names picked without `--value-col`, before falling back to the first numeric column.
+const VALUE_NAMES: [&str; 5] = ["temperature", "temp", "measurement", "measure", "value"];
+
+/// The names of the top-level columns of a Parquet file, which `--station-col` and
+/// `--value-col` may give for their indices.
+pub fn columns(path: &Path) -> io::Result<Vec<String>> {
+    let (_, metadata) = open(path)?;
+    Ok(metadata
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .collect())
+}
+
+fn open(path: &Path) -> io::Result<(File, ArrowReaderMetadata)> {
+    let file = File::open(path)?;
+    let metadata = ArrowReaderMetadata::load(&file, Default::default())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok((file, metadata))
+}
+
+/// Aggregates the station and temperature columns of a Parquet file. Workers claim row groups
+/// from a shared counter and decode only those two columns of each, so a file written with
+/// several row groups is read as many threads wide; one of a single row group is read by one
+/// worker. Rows with a null in either column are skipped and not counted.
+pub fn aggregate(path: &Path, config: &Config, workers: usize) -> Result<Aggregation> {
+    let (file, metadata) = open(path).map_err(Error::open(path))?;
+    let (station, value) =
+        pick_columns(metadata.schema(), config.projection).map_err(Error::read(path))?;
+    let row_groups = metadata.metadata().row_groups();
+    log::verbose!(
+        "parquet: {} row groups, columns {:?} and {:?}",
+        row_groups.len(),
+        metadata.schema().field(station).name(),
+        metadata.schema().field(value).name()
+    );
+
+    let mask = ProjectionMask::roots(metadata.parquet_schema(), [station, value]);
+    // The projected batches keep the file's column order.
+    let columns = match station < value {
+        true => (0, 1),
+        false => (1, 0),
+    };
+    let workers = workers.min(row_groups.len().max(1));
+    let next_row_group = AtomicUsize::new(0);
+    let results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    aggregate_row_groups(
+                        &file,
+                        &metadata,
+                        &mask,
+                        columns,
+                        config.sketches,
+                        &next_row_group,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().map_err(|_| Error::WorkerPanicked))
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    let mut worker_maps = Vec::with_capacity(workers);
+    let mut worker_sketches = Vec::with_capacity(workers);
+    let mut phases = [Duration::ZERO; 2];
+    let mut total_lines = 0;
+    let mut bytes_read = 0;
+    for result in results {
+        let worker = result.map_err(Error::read(path))?;
+        worker_maps.push(worker.station_temperatures);
+        worker_sketches.push(worker.sketches);
+        total_lines += worker.rows;
+        bytes_read += worker.bytes;
+        phases[0] += worker.phases[0];
+        phases[1] += worker.phases[1];
+    }
+    let phase = heap::enter(heap::Phase::Merge);
+    let station_temperatures = merge_tree(worker_maps);
+    let sketches = merge_tree(worker_sketches);
+    drop(phase);
+
+    // Row groups are claimed in order and claimed ones always finish, so the rest starts at
+    // the first column chunk of the next one.
+    let resume_offset = row_groups
+        .get(next_row_group.load(Ordering::Relaxed))
+        .and_then(|row_group| {
+            row_group
+                .columns()
+                .iter()
+                .map(|column| column.byte_range().0)
+                .min()
+        });
+
+    Ok(Aggregation {
+        station_temperatures,
+        total_lines,
+        stats: Stats {
+            workers,
+            bytes_read,
+            phases: vec![("decode", phases[0]), ("aggregate", phases[1])],
+            counters: Vec::new(),
+        },
+        resume_offset,
+        malformed: malformed::Report::default(),
+        sketches,
+    })
+}
+
+/// The top-level columns holding the station and the temperature: those the projection
+/// names, or else the first with a usual name, or else the first of a fitting type.
+fn pick_columns(schema: &Schema, projection: Option<Projection>) -> io::Result<(usize, usize)> {
+    let fields = schema.fields();
+    let (station, value) = match projection {
+        Some(projection) => (
+            Some(projection.station_column),
+            Some(projection.value_column),
+        ),
+        None => {
+            let find = |names: &[&str], fits: fn(&DataType) -> bool| {
+                fields
+                    .iter()
+                    .position(|field| names.contains(&field.name().as_str()))
+                    .or_else(|| fields.iter().position(|field| fits(field.data_type())))
+            };
+            (
+                find(&STATION_NAMES, is_text),
+                find(&VALUE_NAMES, DataType::is_numeric),
+            )
+        }
+    };
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    match (station, value) {
+        (Some(station), Some(value)) if station.max(value) >= fields.len() => {
+            Err(invalid(format!(
+                "no column {}, there are {}",
+                station.max(value),
+                fields.len()
+            )))
+        }
+        (Some(station), Some(value)) if station == value => Err(invalid(format!(
+            "column {} cannot hold both the station and the temperature",
+            station
+        ))),
+        (Some(station), Some(value)) => Ok((station, value)),
+        (None, _) => Err(invalid("no text column for the station".to_string())),
+        (_, None) => Err(invalid("no numeric column for the temperature".to_string())),
+    }
+}
+
+fn is_text(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Utf8
+        | DataType::LargeUtf8
+        | DataType::Utf8View
+        | DataType::Binary
+        | DataType::LargeBinary
+        | DataType::BinaryView => true,
+        DataType::Dictionary(_, values) => is_text(values),
+        _ => false,
+    }
+}
+
+/// What a worker aggregated from the row groups it decoded.
+struct Decoded {
+    station_temperatures: StationTemperatures,
+    rows: u64,
+    /// Compressed bytes of the row groups.
+    bytes: u64,
+    phases: [Duration; 2],
+    sketches: Sketches,
+}
+
+/// A worker's share of `aggregate`: decodes row groups until none are left, or the run is
+/// interrupted.
+fn aggregate_row_groups(
+    file: &File,
+    metadata: &ArrowReaderMetadata,
+    mask: &ProjectionMask,
+    columns: (usize, usize),
+    sketches: sketch::Wanted,
+    next_row_group: &AtomicUsize,
+) -> io::Result<Decoded> {
+    let mut decoded = Decoded {
+        station_temperatures: StationTemperatures::default(),
+        rows: 0,
+        bytes: 0,
+        phases: [Duration::ZERO; 2],
+        sketches: Sketches::new(sketches),
+    };
+    let mut table = StationTable::new();
+    let row_groups = metadata.metadata().row_groups();
+    while !signals::interrupted() {
+        signals::wait_while_paused();
+        let index = next_row_group.fetch_add(1, Ordering::Relaxed);
+        let Some(row_group) = row_groups.get(index) else {
+            break;
+        };
+
+        let mut batches =
+            ParquetRecordBatchReaderBuilder::new_with_metadata(file.try_clone()?, metadata.clone())
+                .with_row_groups(vec![index])
+                .with_projection(mask.clone())
+                .with_batch_size(BATCH_ROWS)
+                .build()
+                .map_err(io::Error::other)?;
+        loop {
+            let decode_start = Instant::now();
+            let phase = heap::enter(heap::Phase::Read);
+            let Some(batch) = batches.next() else {
+                break;
+            };
+            let batch = batch.map_err(io::Error::other)?;
+            drop(phase);
+            decoded.phases[0] += decode_start.elapsed();
+
+            let aggregate_start = Instant::now();
+            let phase = heap::enter(heap::Phase::Parse);
+            decoded.rows += add_batch(&batch, columns, &mut decoded.sketches, &mut table)? as u64;
+            drop(phase);
+            decoded.phases[1] += aggregate_start.elapsed();
+        }
+        decoded.bytes += row_group.compressed_size() as u64;
+    }
+    decoded.station_temperatures = table.into_map();
+    Ok(decoded)
+}
+
+/// Adds the rows of `batch` to `table`, the temperature rounded to tenths, and to the worker's
+/// `sketches`.
+fn add_batch(
+    batch: &RecordBatch,
+    columns: (usize, usize),
+    sketches: &mut Sketches,
+    table: &mut StationTable,
+) -> io::Result<u32> {
+    let stations =
+        arrow_cast::cast(batch.column(columns.0), &DataType::Binary).map_err(io::Error::other)?;
+    let values =
+        arrow_cast::cast(batch.column(columns.1), &DataType::Float64).map_err(io::Error::other)?;
+    let (stations, values) = (
+        stations.as_binary::<i32>(),
+        values.as_primitive::<Float64Type>(),
+    );
+
+    let mut rows = 0;
+    for (station, value) in stations.iter().zip(values.iter()) {
+        let (Some(station), Some(value)) = (station, value) else {
+            continue;
+        };
+        let (station, temperature) = (key_prefix(station), (value * 10.0).round() as i32);
+        table.add(station, temperature);
+        sketches.add(station, temperature);
+        rows += 1;
+    }
+    Ok(rows)
+}
@@ -0,0 +1,76 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use perf_event::events::Hardware;
+use perf_event::{Builder, Counter, Group};
+
+use crate::log;
+
+/// Counters read around every parse, in the order `take_totals` reports them.
+const EVENTS: [(&str, Hardware); 4] = [
+    ("cycles", Hardware::CPU_CYCLES),
+    ("instructions", Hardware::INSTRUCTIONS),
+    ("cache misses", Hardware::CACHE_MISSES),
+    ("branch misses", Hardware::BRANCH_MISSES),
+];
+
+static TOTALS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+// Every worker fails the same way when counters are unavailable; say it once.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// A group of hardware counters observing the calling thread.
+pub struct ParseCounters {
+    group: Group,
+    counters: Vec<Counter>,
+}
+
+impl ParseCounters {
+    /// Starts counting, or returns `None` when the kernel refuses (for example because of
+    /// `perf_event_paranoid` or a virtual machine without a PMU).
+    pub fn start() -> Option<ParseCounters> {
+        match ParseCounters::open() {
+            Ok(counters) => Some(counters),
+            Err(err) => {
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    log::info!("Hardware counters unavailable: {}", err);
+                }
+                None
+            }
+        }
+    }
+
+    fn open() -> io::Result<ParseCounters> {
+        let mut group = Group::new()?;
+        let mut counters = Vec::with_capacity(EVENTS.len());
+        for (_, event) in EVENTS {
+            counters.push(Builder::new().group(&mut group).kind(event).build()?);
+        }
+        group.enable()?;
+        Ok(ParseCounters { group, counters })
+    }
+
+    /// Stops counting and adds the counts to the run totals.
+    pub fn stop(mut self) {
+        if self.group.disable().is_err() {
+            return;
+        }
+        if let Ok(counts) = self.group.read() {
+            for (total, counter) in TOTALS.iter().zip(&self.counters) {
+                total.fetch_add(counts[counter], Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Counts summed over every parse since the last call, empty if none could be counted.
+pub fn take_totals() -> Vec<(&'static str, u64)> {
+    let totals: Vec<_> = EVENTS
+        .iter()
+        .zip(&TOTALS)
+        .map(|((name, _), total)| (*name, total.swap(0, Ordering::Relaxed)))
+        .collect();
+    if totals.iter().all(|(_, count)| *count == 0) {
+        return Vec::new();
+    }
+    totals
+}
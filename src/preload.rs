@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::time;
+
+#[cfg(unix)]
+use memmap2::Advice;
+use memmap2::Mmap;
+
+use crate::limits::Limits;
+use crate::{log, read_at};
+
+/// The whole input in memory, either read into a buffer or mapped. It lives as long as the
+/// run holding it, which frees or unmaps it when done.
+#[derive(Debug)]
+pub enum Preloaded {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Preloaded {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Preloaded::Read(data) => data,
+            Preloaded::Mapped(mapping) => mapping,
+        }
+    }
+}
+
+/// Bytes read between two progress updates.
+const PROGRESS_STEP: usize = 256 << 20;
+
+/// Reads all of `path` into memory, so the timed runs never touch storage.
+///
+/// Files larger than `max_fraction` of the available memory (the smaller of what the kernel
+/// reports and the cgroup limit) are mapped instead, leaving it to the page cache to keep
+/// them resident.
+pub fn load(path: &Path, max_fraction: f64, limits: &Limits) -> io::Result<Preloaded> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+
+    let available = available_memory().into_iter().chain(limits.memory).min();
+    if let Some(available) = available {
+        if len as f64 > available as f64 * max_fraction {
+            log::info!(
+                "Input is {} MiB but only {:.0}% of {} MiB available memory may be preloaded, mapping it instead",
+                len >> 20,
+                max_fraction * 100.0,
+                available >> 20
+            );
+            return map(path);
+        }
+    }
+
+    let start = time::Instant::now();
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let loaded = data.len();
+        let end = (loaded + PROGRESS_STEP).min(len);
+        if read_at(&file, &mut data, end, loaded as u64)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if log::enabled(log::Level::Normal) {
+            eprint!("\rPreloading: {} / {} MiB", data.len() >> 20, len >> 20);
+            let _ = io::stderr().flush();
+        }
+    }
+    if log::enabled(log::Level::Normal) {
+        eprintln!();
+    }
+    log::info!("Preloaded {} MiB in {:?}", len >> 20, start.elapsed());
+
+    Ok(Preloaded::Read(data))
+}
+
+/// MemAvailable from `/proc/meminfo`, in bytes.
+#[cfg(target_os = "linux")]
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib << 10)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn available_memory() -> Option<u64> {
+    None
+}
+
+/// Maps all of `path` read only. Workers slice the mapping, parsing straight out of the page
+/// cache.
+pub fn map(path: &Path) -> io::Result<Preloaded> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is read only. Truncating the file underneath it would fault, as with
+    // any mmap.
+    let mapping = unsafe { Mmap::map(&file)? };
+    #[cfg(unix)]
+    mapping.advise(Advice::Sequential)?;
+    Ok(Preloaded::Mapped(mapping))
+}
@@ -0,0 +1,42 @@
+#[cfg(feature = "cli")]
+pub use indicatif::ProgressBar;
+#[cfg(feature = "cli")]
+use indicatif::ProgressStyle;
+
+/// A progress bar over `len` input bytes, or a byte counter for streams of unknown length.
+/// Hidden unless `enabled`, so callers can update it unconditionally.
+#[cfg(feature = "cli")]
+pub fn bar(enabled: bool, len: Option<u64>) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    match len {
+        Some(len) => ProgressBar::new(len).with_style(
+            ProgressStyle::with_template(
+                "{bar:40} {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec} ETA {eta}",
+            )
+            .expect("valid progress template"),
+        ),
+        None => ProgressBar::no_length().with_style(
+            ProgressStyle::with_template("{spinner} {binary_bytes} {binary_bytes_per_sec}")
+                .expect("valid progress template"),
+        ),
+    }
+}
+
+/// Without the `cli` feature there is no terminal to draw on; the bar is always hidden.
+#[cfg(not(feature = "cli"))]
+pub fn bar(_enabled: bool, _len: Option<u64>) -> ProgressBar {
+    ProgressBar
+}
+
+#[cfg(not(feature = "cli"))]
+#[derive(Clone)]
+pub struct ProgressBar;
+
+#[cfg(not(feature = "cli"))]
+impl ProgressBar {
+    pub fn inc(&self, _delta: u64) {}
+
+    pub fn finish(&self) {}
+}
@@ -0,0 +1,190 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::hint::black_box;
+use std::str::FromStr;
+
+use ahash::AHashMap;
+
+use crate::{key_prefix, Key, StationTemperatures, Tenths, WeatherData};
+
+/// How the fields of a wider row are delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum InputFormat {
+    /// `;`-separated fields, like the measurements themselves
+    Measurements,
+    /// `,`-separated fields, each optionally in double quotes, as spreadsheets and databases
+    /// export them
+    Csv,
+}
+
+/// Which columns of a wider row hold the station name and the temperature.
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    pub station_column: usize,
+    pub value_column: usize,
+    pub format: InputFormat,
+}
+
+impl Projection {
+    /// Picks the station (cut to what a `Key` keeps) and temperature in tenths out of one line,
+    /// `None` when the line lacks either column or the temperature is not a number (a header
+    /// row, for instance).
+    #[doc(hidden)]
+    pub fn parse_line<'a>(&self, line: &'a [u8]) -> Option<(&'a [u8], i32)> {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let last_column = self.station_column.max(self.value_column);
+        let mut station = None;
+        let mut value = None;
+        // Columns past the last wanted one are never split.
+        for (column, field) in fields(line, self.format).take(last_column + 1).enumerate() {
+            if column == self.station_column {
+                station = Some(field);
+            }
+            if column == self.value_column {
+                value = Some(field);
+            }
+        }
+
+        let station = station?;
+        let temperature = Tenths::parse(std::str::from_utf8(value?).ok()?)?;
+
+        Some((key_prefix(station), temperature.0 as i32))
+    }
+}
+
+/// A column given by its zero-based index, or by its name in the header row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[doc(hidden)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for Column {
+    type Err = Infallible;
+
+    fn from_str(column: &str) -> Result<Column, Infallible> {
+        Ok(column
+            .parse()
+            .map_or_else(|_| Column::Name(column.to_string()), Column::Index))
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Column::Index(index) => write!(f, "{}", index),
+            Column::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+impl Column {
+    /// The index of the column among the columns called `names`, `None` for a name it lacks.
+    pub fn resolve(&self, names: &[Vec<u8>]) -> Option<usize> {
+        match self {
+            Column::Index(index) => Some(*index),
+            Column::Name(name) => names.iter().position(|field| field == name.as_bytes()),
+        }
+    }
+}
+
+/// The column names a header row gives, read like the rows below it.
+#[doc(hidden)]
+pub fn header_names(header: &[u8], format: InputFormat) -> Vec<Vec<u8>> {
+    // Spreadsheets tend to start their exports with a byte order mark.
+    let header = header.strip_prefix("\u{feff}".as_bytes()).unwrap_or(header);
+    let header = header.strip_suffix(b"\n").unwrap_or(header);
+    let header = header.strip_suffix(b"\r").unwrap_or(header);
+    fields(header, format).map(<[u8]>::to_vec).collect()
+}
+
+/// The fields of `line`, without the quotes around CSV fields. A quoted field may hold the
+/// separator; a doubled quote inside it is kept as two quotes, and a quoted newline ends the
+/// row like any other.
+fn fields(line: &[u8], format: InputFormat) -> impl Iterator<Item = &[u8]> {
+    let mut rest = Some(line);
+    std::iter::from_fn(move || {
+        let line = rest?;
+        let end = match format {
+            InputFormat::Measurements => memchr::memchr(b';', line),
+            InputFormat::Csv => {
+                let mut quoted = false;
+                line.iter().position(|&b| {
+                    quoted ^= b == b'"';
+                    b == b',' && !quoted
+                })
+            }
+        };
+        let field = match end {
+            Some(end) => {
+                rest = Some(&line[end + 1..]);
+                &line[..end]
+            }
+            None => {
+                rest = None;
+                line
+            }
+        };
+        Some(match format {
+            InputFormat::Measurements => field,
+            InputFormat::Csv => field
+                .strip_prefix(b"\"")
+                .and_then(|field| field.strip_suffix(b"\""))
+                .unwrap_or(field),
+        })
+    })
+}
+
+/// Counterpart of `process_buffer` for rows with extra columns; rows that cannot be
+/// projected are skipped and not counted. `sketch` sees every row that is.
+#[doc(hidden)]
+pub fn process_buffer(
+    buf: &[u8],
+    projection: &Projection,
+    mut sketch: impl FnMut(&[u8], i32),
+) -> (StationTemperatures, u32) {
+    let mut station_temperatures: StationTemperatures = AHashMap::with_capacity(1000);
+    let mut lines_count = 0;
+
+    for line in buf.split(|&b| b == b'\n') {
+        let Some((station, temperature)) = projection.parse_line(line) else {
+            continue;
+        };
+        if let Some(data) = station_temperatures.get_mut(station) {
+            data.add_temperature(temperature);
+        } else {
+            station_temperatures.insert(
+                Key::new(station),
+                WeatherData {
+                    total_temperature: temperature as i64,
+                    count: 1,
+                    min_temperature: temperature,
+                    max_temperature: temperature,
+                    mean_temperature: 0,
+                },
+            );
+        }
+        sketch(station, temperature);
+        lines_count += 1;
+    }
+
+    (station_temperatures, lines_count)
+}
+
+/// Counterpart of `parse_buffer`: projects and parses every row without aggregating.
+#[doc(hidden)]
+pub fn parse_buffer(buf: &[u8], projection: &Projection) -> u32 {
+    let mut temperature_sum = 0i64;
+    let mut lines_count = 0;
+    for line in buf.split(|&b| b == b'\n') {
+        if let Some((station, temperature)) = projection.parse_line(line) {
+            black_box(station);
+            temperature_sum += temperature as i64;
+            lines_count += 1;
+        }
+    }
+    black_box(temperature_sum);
+    lines_count
+}
@@ -0,0 +1,46 @@
+use std::io;
+use std::path::PathBuf;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::Aggregator;
+
+/// Aggregates the measurements at `path` into `{station: (min, mean, max, count)}`, in degrees
+/// and sorted by station. The GIL is released while the workers run, so other Python threads
+/// keep going meanwhile.
+#[pyfunction]
+#[pyo3(signature = (path, threads=None))]
+fn process_file<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    threads: Option<usize>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let mut builder = Aggregator::builder();
+    if let Some(threads) = threads {
+        builder = builder.threads(threads);
+    }
+    let aggregator = builder
+        .build()
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    // Through `io::Error`, so a missing file raises `FileNotFoundError` and so on.
+    let report = py
+        .detach(|| aggregator.process_file(&path))
+        .map_err(io::Error::from)?;
+
+    let stations = PyDict::new(py);
+    for station in report.iter() {
+        stations.set_item(
+            station.name,
+            (station.min, station.mean, station.max, station.count),
+        )?;
+    }
+    Ok(stations)
+}
+
+/// The `onebrc` Python module.
+#[pymodule]
+fn onebrc(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(process_file, module)?)
+}
@@ -0,0 +1,494 @@
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use ureq::http::{Response, StatusCode};
+use ureq::{Agent, Body};
+
+use crate::error::{Error, Result};
+use crate::sharded::{self, ShardedMap};
+use crate::sketch::Sketches;
+use crate::stats::Stats;
+use crate::{
+    compressed, heap, log, malformed, merge_into, merge_tree, process_lines, process_thread,
+    progress, signals, stream, Aggregation, Config, MapLayout, StationTemperatures, Worker,
+};
+
+/// Aggregates the object at `url`, an `https://` or `http://` URL or `s3://bucket/key`.
+///
+/// Where the server honours `Range` requests, every worker downloads chunks of its own, the
+/// way `aggregate_text` reads them from a file, so the download runs as many connections wide
+/// as there are workers. Servers that do not, and gzip or zstd objects, are streamed.
+pub fn aggregate(url: &Path, config: &Config, workers: usize) -> Result<Aggregation> {
+    let object = Object::parse(url.to_str().unwrap_or_default()).map_err(Error::open(url))?;
+    let agent = Agent::config_builder()
+        .timeout_connect(Some(Duration::from_secs(30)))
+        .build()
+        .new_agent();
+
+    let format = compressed::Format::detect(url);
+    if format != compressed::Format::Plain {
+        let body = object.get(&agent, None).map_err(Error::open(url))?;
+        let decoder =
+            compressed::decode(body.into_body().into_reader(), format).map_err(Error::open(url))?;
+        return stream::aggregate(decoder, url, config, workers);
+    }
+
+    // Only the first byte: a server that honours ranges says how long the object is in
+    // `Content-Range`, and one that does not sends all of it.
+    let probe = match object.call(&agent, Some(0..1)) {
+        Ok(probe) => probe,
+        // Nothing to range over in an empty object.
+        Err(ureq::Error::StatusCode(416)) => {
+            return stream::aggregate(io::empty(), url, config, workers)
+        }
+        Err(err) => return Err(Error::open(url)(http_error(err))),
+    };
+    let len = match probe.status() {
+        StatusCode::PARTIAL_CONTENT => probe
+            .headers()
+            .get("content-range")
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| range.rsplit_once('/'))
+            .and_then(|(_, len)| len.parse::<usize>().ok()),
+        _ => None,
+    };
+    let Some(len) = len else {
+        log::info!(
+            "{} does not serve byte ranges, streaming it on one connection",
+            url.display()
+        );
+        let body = match probe.status() {
+            StatusCode::PARTIAL_CONTENT => object.get(&agent, None).map_err(Error::open(url))?,
+            _ => probe,
+        };
+        return stream::aggregate(body.into_body().into_reader(), url, config, workers);
+    };
+    ranged(&object, &agent, url, len, config, workers)
+}
+
+/// `aggregate` for a server that honours ranges: the workers claim chunks off a shared
+/// cursor and download each with a request of its own.
+fn ranged(
+    object: &Object,
+    agent: &Agent,
+    url: &Path,
+    len: usize,
+    config: &Config,
+    workers: usize,
+) -> Result<Aggregation> {
+    let chunk_size = config.chunk_size;
+    let extra_buffer_size = config.extra_buffer_size();
+    let chunk_count = len.div_ceil(chunk_size);
+    log::verbose!("chunks: {:?}", chunk_count);
+
+    let workers = workers.min(chunk_count);
+    let next_chunk = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let aborted = AtomicBool::new(false);
+    let sharded = (config.map == MapLayout::Sharded).then(|| ShardedMap::new(workers));
+    let progress = progress::bar(config.progress, Some(len as u64));
+    let results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| -> io::Result<Downloaded> {
+                    let mut downloaded = Downloaded {
+                        station_temperatures: StationTemperatures::default(),
+                        lines: 0,
+                        bytes: 0,
+                        phases: [Duration::ZERO; 2],
+                        malformed: malformed::Report::default(),
+                        sketches: Sketches::default(),
+                    };
+                    let mut worker = Worker::new(config);
+                    let mut buf = Vec::new();
+                    while !signals::interrupted()
+                        && !aborted.load(Ordering::Relaxed)
+                        && !failed.load(Ordering::Relaxed)
+                    {
+                        signals::wait_while_paused();
+                        // A claimed chunk is always finished, which is what `resume_offset`
+                        // relies on.
+                        let index = next_chunk.fetch_add(1, Ordering::Relaxed);
+                        if index >= chunk_count {
+                            break;
+                        }
+                        let start = index * chunk_size;
+                        let end = (start + chunk_size + extra_buffer_size).min(len);
+
+                        let download_start = Instant::now();
+                        let phase = heap::enter(heap::Phase::Read);
+                        object
+                            .read_range(agent, start..end, &mut buf)
+                            .inspect_err(|_| failed.store(true, Ordering::Relaxed))?;
+                        drop(phase);
+                        downloaded.phases[0] += download_start.elapsed();
+                        downloaded.bytes += buf.len() as u64;
+
+                        let parse_start = Instant::now();
+                        let phase = heap::enter(heap::Phase::Parse);
+                        if start == 0 {
+                            // The chunk skips the first line, like every chunk skips the
+                            // partial line it starts in.
+                            let first_end =
+                                memchr::memchr(b'\n', &buf).map_or(buf.len(), |i| i + 1);
+                            let (chunk_temperatures, lines_count) =
+                                process_lines(&buf[..first_end], 0, config, &mut worker);
+                            downloaded.lines += lines_count as u64;
+                            merge_into(&mut downloaded.station_temperatures, chunk_temperatures);
+                        }
+                        let (chunk_temperatures, lines_count) = process_thread(
+                            &buf,
+                            start as u64,
+                            chunk_size,
+                            extra_buffer_size,
+                            config,
+                            &mut worker,
+                        );
+                        drop(phase);
+                        downloaded.lines += lines_count as u64;
+                        if worker.malformed.aborted() {
+                            aborted.store(true, Ordering::Relaxed);
+                        }
+
+                        let phase = heap::enter(heap::Phase::Merge);
+                        sharded::fold(
+                            sharded.as_ref(),
+                            &mut downloaded.station_temperatures,
+                            &mut worker.table,
+                            chunk_temperatures,
+                        );
+                        drop(phase);
+                        downloaded.phases[1] += parse_start.elapsed();
+                        progress.inc((end - start).min(chunk_size) as u64);
+                    }
+                    merge_into(
+                        &mut downloaded.station_temperatures,
+                        worker.table.into_map(),
+                    );
+                    downloaded.malformed = worker.malformed;
+                    downloaded.sketches = worker.sketches;
+                    Ok(downloaded)
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().map_err(|_| Error::WorkerPanicked))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    progress.finish();
+
+    let mut worker_maps = Vec::with_capacity(workers + 1);
+    let mut phases = [Duration::ZERO; 2];
+    let mut total_lines = 0;
+    let mut bytes_read = 0;
+    let mut worker_sketches = Vec::with_capacity(workers);
+    let mut malformed = malformed::Report::default();
+    for result in results {
+        let downloaded = result.map_err(Error::read(url))?;
+        worker_maps.push(downloaded.station_temperatures);
+        worker_sketches.push(downloaded.sketches);
+        malformed.merge(downloaded.malformed, 0);
+        total_lines += downloaded.lines;
+        bytes_read += downloaded.bytes;
+        phases[0] += downloaded.phases[0];
+        phases[1] += downloaded.phases[1];
+    }
+
+    // Claimed chunks were finished, so everything before the next chunk's first line is done.
+    let resume_offset = match next_chunk.load(Ordering::Relaxed) {
+        0 => Some(0),
+        done if done < chunk_count => {
+            let start = done * chunk_size;
+            let mut buf = Vec::new();
+            object
+                .read_range(agent, start..(start + extra_buffer_size).min(len), &mut buf)
+                .map_err(Error::read(url))?;
+            // Without a newline in reach the chunk's first line was left unfinished too.
+            match memchr::memchr(b'\n', &buf) {
+                Some(i) => {
+                    Some((start + i + 1) as u64).filter(|&line_start| line_start < len as u64)
+                }
+                None => Some(start as u64),
+            }
+        }
+        _ => None,
+    };
+
+    let phase = heap::enter(heap::Phase::Merge);
+    worker_maps.extend(sharded.map(ShardedMap::into_map));
+    let station_temperatures = merge_tree(worker_maps);
+    let sketches = merge_tree(worker_sketches);
+    drop(phase);
+
+    let stats = Stats {
+        workers,
+        bytes_read,
+        phases: vec![("download", phases[0]), ("parse", phases[1])],
+        counters: Vec::new(),
+    };
+
+    Ok(Aggregation {
+        station_temperatures,
+        total_lines,
+        stats,
+        resume_offset,
+        malformed,
+        sketches,
+    })
+}
+
+/// What a worker downloaded and found in it.
+struct Downloaded {
+    station_temperatures: StationTemperatures,
+    lines: u64,
+    bytes: u64,
+    phases: [Duration; 2],
+    malformed: malformed::Report,
+    sketches: Sketches,
+}
+
+/// Where a remote input is downloaded from.
+struct Object {
+    url: String,
+    /// For `s3://` inputs with credentials in the environment, which sign every request.
+    s3: Option<S3>,
+}
+
+/// An S3 object and the credentials to sign requests for it with.
+pub struct S3 {
+    pub host: String,
+    /// The URI-encoded path of the object on `host`.
+    pub path: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl Object {
+    /// `s3://bucket/key` is fetched from the bucket's virtual host in `AWS_REGION`, or with a
+    /// path-style URL from `AWS_ENDPOINT_URL` when that is set, as S3-compatible stores want.
+    /// Without `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` the requests are not signed,
+    /// which public buckets allow.
+    fn parse(input: &str) -> io::Result<Object> {
+        let Some(location) = input.strip_prefix("s3://") else {
+            return Ok(Object {
+                url: input.to_string(),
+                s3: None,
+            });
+        };
+        let (bucket, key) = location
+            .split_once('/')
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not s3://bucket/key", input),
+                )
+            })?;
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let (url, host, path) = match var("AWS_ENDPOINT_URL_S3").or_else(|| var("AWS_ENDPOINT_URL"))
+        {
+            Some(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let host = endpoint
+                    .split_once("://")
+                    .map_or(endpoint, |(_, host)| host);
+                let path = format!("/{}/{}", uri_encode(bucket), uri_encode(key));
+                (format!("{}{}", endpoint, path), host.to_string(), path)
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", bucket, region);
+                let path = format!("/{}", uri_encode(key));
+                (format!("https://{}{}", host, path), host, path)
+            }
+        };
+        let s3 = var("AWS_ACCESS_KEY_ID")
+            .zip(var("AWS_SECRET_ACCESS_KEY"))
+            .map(|(access_key_id, secret_access_key)| S3 {
+                host,
+                path,
+                region,
+                access_key_id,
+                secret_access_key,
+                session_token: var("AWS_SESSION_TOKEN"),
+            });
+        Ok(Object { url, s3 })
+    }
+
+    /// A `GET` of `range`, or of all of the object.
+    fn call(
+        &self,
+        agent: &Agent,
+        range: Option<Range<usize>>,
+    ) -> std::result::Result<Response<Body>, ureq::Error> {
+        let range = range.map(|range| format!("bytes={}-{}", range.start, range.end - 1));
+        let mut request = agent.get(&self.url);
+        if let Some(range) = &range {
+            request = request.header("range", range);
+        }
+        if let Some(s3) = &self.s3 {
+            for (name, value) in s3.sign(range.as_deref(), SystemTime::now()) {
+                request = request.header(name, value);
+            }
+        }
+        request.call()
+    }
+
+    fn get(&self, agent: &Agent, range: Option<Range<usize>>) -> io::Result<Response<Body>> {
+        self.call(agent, range).map_err(http_error)
+    }
+
+    /// Replaces what `buf` holds with the bytes of `range`.
+    fn read_range(&self, agent: &Agent, range: Range<usize>, buf: &mut Vec<u8>) -> io::Result<()> {
+        let len = range.len();
+        buf.clear();
+        self.get(agent, Some(range))?
+            .into_body()
+            .into_reader()
+            // One byte more tells a server that ignored the range from a short object.
+            .take(len as u64 + 1)
+            .read_to_end(buf)?;
+        if buf.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("asked for {} bytes, got {}", len, buf.len()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl S3 {
+    /// The headers that sign a `GET` of the object with AWS Signature Version 4.
+    pub fn sign(&self, range: Option<&str>, now: SystemTime) -> Vec<(&'static str, String)> {
+        let timestamp = amz_date(now);
+        let date = &timestamp[..8];
+        let payload_hash = hex(&Sha256::digest(b""));
+
+        let mut headers = vec![("host", self.host.clone())];
+        headers.extend(range.map(|range| ("range", range.to_string())));
+        headers.push(("x-amz-content-sha256", payload_hash.clone()));
+        headers.push(("x-amz-date", timestamp.clone()));
+        headers.extend(
+            self.session_token
+                .clone()
+                .map(|token| ("x-amz-security-token", token)),
+        );
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect::<String>();
+        let canonical_request = format!(
+            "GET\n{}\n\n{}\n{}\n{}",
+            self.path, canonical_headers, signed_headers, payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = [date, &self.region, "s3", "aws4_request"].iter().fold(
+            format!("AWS4{}", self.secret_access_key).into_bytes(),
+            |key, part| hmac(&key, part.as_bytes()),
+        );
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        // `host` and `range` go out with the request itself.
+        headers.retain(|(name, _)| !matches!(*name, "host" | "range"));
+        headers.push((
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key_id, scope, signed_headers, signature
+            ),
+        ));
+        headers
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encodes all but the unreserved characters and `/`, as S3 signs paths.
+fn uri_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for &byte in path.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// `now` as `20130524T000000Z`, in UTC.
+fn amz_date(now: SystemTime) -> String {
+    let seconds = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    // Days to a civil date, after Howard Hinnant's `civil_from_days`.
+    let days = days + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// `io::Error`s for HTTP failures, with the kinds of the file errors they stand for.
+fn http_error(err: ureq::Error) -> io::Error {
+    match err {
+        ureq::Error::StatusCode(status) => {
+            let kind = match status {
+                401 | 403 => io::ErrorKind::PermissionDenied,
+                404 => io::ErrorKind::NotFound,
+                _ => io::ErrorKind::Other,
+            };
+            io::Error::new(kind, format!("HTTP status {}", status))
+        }
+        err => err.into_io(),
+    }
+}
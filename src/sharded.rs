@@ -0,0 +1,67 @@
+use std::sync::Mutex;
+
+use ahash::RandomState;
+
+use crate::table::StationTable;
+use crate::{merge_into, StationTemperatures, WeatherData};
+
+/// Station totals shared by every worker, for `--map sharded`. Stations are spread over the
+/// shards by hash and every shard has a lock of its own, so workers flushing different
+/// stations rarely wait on each other, and the run holds one map of every station instead of
+/// one per worker.
+pub struct ShardedMap {
+    hasher: RandomState,
+    shards: Box<[Mutex<StationTemperatures>]>,
+}
+
+impl ShardedMap {
+    /// Enough shards that `workers` flushing at once seldom pick the same one.
+    pub fn new(workers: usize) -> ShardedMap {
+        ShardedMap {
+            hasher: RandomState::new(),
+            shards: (0..(workers * 4).next_power_of_two())
+                .map(|_| Mutex::new(StationTemperatures::default()))
+                .collect(),
+        }
+    }
+
+    /// Adds a chunk's totals.
+    pub fn merge(&self, from: StationTemperatures) {
+        for (station_name, data) in from {
+            let shard = self.hasher.hash_one(station_name) as usize % self.shards.len();
+            self.shards[shard]
+                .lock()
+                .unwrap()
+                .entry(station_name)
+                .and_modify(|existing: &mut WeatherData| existing.merge(&data))
+                .or_insert(data);
+        }
+    }
+
+    /// All shards in one map. A station only ever lands in one shard, so this just moves the
+    /// entries over.
+    pub fn into_map(self) -> StationTemperatures {
+        let mut station_temperatures = StationTemperatures::default();
+        for shard in self.shards {
+            merge_into(&mut station_temperatures, shard.into_inner().unwrap());
+        }
+        station_temperatures
+    }
+}
+
+/// Folds one parsed chunk into wherever `--map` keeps the totals. The worker's `table` keeps
+/// accumulating unless the totals are shared, when it is emptied into the shared map.
+pub fn fold(
+    sharded: Option<&ShardedMap>,
+    worker_temperatures: &mut StationTemperatures,
+    table: &mut StationTable,
+    chunk_temperatures: StationTemperatures,
+) {
+    match sharded {
+        Some(sharded) => {
+            sharded.merge(table.take());
+            sharded.merge(chunk_temperatures);
+        }
+        None => merge_into(worker_temperatures, chunk_temperatures),
+    }
+}
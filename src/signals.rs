@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "cli")]
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(all(feature = "cli", unix))]
+use signal_hook::consts::{SIGUSR1, SIGUSR2};
+#[cfg(feature = "cli")]
+use signal_hook::flag;
+
+use crate::log;
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+static PAUSED: OnceLock<Arc<AtomicUsize>> = OnceLock::new();
+static PAUSED_NANOS: AtomicU64 = AtomicU64::new(0);
+static WAITERS: Mutex<(usize, Option<Instant>)> = Mutex::new((0, None));
+
+/// Makes the first Ctrl-C or SIGTERM request a graceful stop; a second one exits immediately.
+/// On unix, SIGUSR1 pauses chunk scheduling and SIGUSR2 resumes it. Without the `cli` feature
+/// the library leaves the process's signals alone and runs are never interrupted.
+#[cfg(feature = "cli")]
+pub fn install() {
+    let interrupted = INTERRUPTED.get_or_init(|| Arc::new(AtomicBool::new(false)));
+    for (signal, status) in [(SIGINT, 130), (SIGTERM, 143)] {
+        // Registered first so it only sees the flag as set on a repeated signal.
+        let _ = flag::register_conditional_shutdown(signal, status, Arc::clone(interrupted));
+        let _ = flag::register(signal, Arc::clone(interrupted));
+    }
+
+    #[cfg(unix)]
+    {
+        let paused = PAUSED.get_or_init(|| Arc::new(AtomicUsize::new(0)));
+        let _ = flag::register_usize(SIGUSR1, Arc::clone(paused), 1);
+        let _ = flag::register_usize(SIGUSR2, Arc::clone(paused), 0);
+    }
+}
+
+/// True once Ctrl-C was pressed or SIGTERM arrived; workers should stop picking up new chunks.
+pub fn interrupted() -> bool {
+    INTERRUPTED
+        .get()
+        .is_some_and(|interrupted| interrupted.load(Ordering::Relaxed))
+}
+
+fn paused() -> bool {
+    PAUSED
+        .get()
+        .is_some_and(|paused| paused.load(Ordering::Relaxed) == 1)
+}
+
+/// Blocks while scheduling is paused, unless the run gets interrupted meanwhile.
+pub fn wait_while_paused() {
+    if !paused() {
+        return;
+    }
+
+    // Several workers may wait at once; the pause lasts from the first one in to the last one out.
+    {
+        let mut waiters = WAITERS.lock().unwrap();
+        if waiters.0 == 0 {
+            waiters.1 = Some(Instant::now());
+            log::info!("Paused, send SIGUSR2 to resume");
+        }
+        waiters.0 += 1;
+    }
+
+    while paused() && !interrupted() {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let mut waiters = WAITERS.lock().unwrap();
+    waiters.0 -= 1;
+    if waiters.0 == 0 {
+        if let Some(paused_at) = waiters.1.take() {
+            let paused_for = paused_at.elapsed();
+            PAUSED_NANOS.fetch_add(paused_for.as_nanos() as u64, Ordering::Relaxed);
+            log::info!("Resumed after {:?}", paused_for);
+        }
+    }
+}
+
+/// Exits with status 1 unless the process is gone within `grace` of a stop request, for
+/// services that flush their state on the way out.
+pub fn exit_after_grace_period(grace: Duration) {
+    thread::spawn(move || {
+        while !interrupted() {
+            thread::sleep(Duration::from_millis(50));
+        }
+        thread::sleep(grace);
+        eprintln!("Did not stop within the {:?} grace period, exiting", grace);
+        std::process::exit(1);
+    });
+}
+
+/// Total wall time scheduling spent paused.
+pub fn paused_duration() -> Duration {
+    Duration::from_nanos(PAUSED_NANOS.load(Ordering::Relaxed))
+}
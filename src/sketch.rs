@@ -0,0 +1,372 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use ahash::AHashMap;
+
+use crate::{log, station_name, Key, Tenths};
+
+/// How many centroids a digest keeps, roughly; more is more accurate at the tails.
+const COMPRESSION: f64 = 100.0;
+/// Values a digest buffers before folding them into its centroids.
+const BUFFER_SIZE: usize = 500;
+/// The hottest temperature of the 1BRC contract, in tenths; the coldest is its negative.
+const HOTTEST: i32 = 999;
+/// Bytes of the longest file name most file systems take.
+const NAME_MAX: usize = 255;
+
+/// What the workers collect per station, besides the totals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Wanted {
+    /// A t-digest, for the percentiles of `--stats`.
+    pub digests: bool,
+    /// Exact counts per tenth of a degree, for `--histogram`.
+    pub histograms: bool,
+}
+
+impl Wanted {
+    pub fn any(self) -> bool {
+        self.digests || self.histograms
+    }
+}
+
+/// A percentile asked for with `--stats`, such as `p99` or `p99.9`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentile(f64);
+
+impl Percentile {
+    /// The fraction of the measurements at or below it.
+    fn quantile(self) -> f64 {
+        self.0 / 100.0
+    }
+
+    /// Its name as a column of the machine-readable formats, `p99_9` for `p99.9`.
+    pub fn column(self) -> String {
+        self.to_string().replace('.', "_")
+    }
+}
+
+impl FromStr for Percentile {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Percentile, String> {
+        let percent = text
+            .strip_prefix('p')
+            .and_then(|percent| percent.parse::<f64>().ok())
+            .filter(|percent| (0.0..=100.0).contains(percent))
+            .ok_or_else(|| format!("`{}` is not a percentile from p0 to p100", text))?;
+        Ok(Percentile(percent))
+    }
+}
+
+impl fmt::Display for Percentile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "p{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// A merging t-digest of one station's temperatures, in tenths. Values are buffered and
+/// folded into centroids that are small near both ends of the distribution, where the
+/// percentiles people ask for are, and large in the middle, so it stays a few KB however many
+/// measurements it saw and two digests merge by folding the centroids of one into the other.
+#[derive(Debug, Default)]
+struct Digest {
+    /// Ordered by mean.
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    min: f64,
+    max: f64,
+}
+
+impl Digest {
+    fn add(&mut self, value: f64) {
+        if self.centroids.is_empty() && self.buffer.is_empty() {
+            (self.min, self.max) = (value, value);
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.buffer.push(Centroid {
+            mean: value,
+            weight: 1.0,
+        });
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.compress();
+        }
+    }
+
+    fn merge(&mut self, mut other: Digest) {
+        if other.centroids.is_empty() && other.buffer.is_empty() {
+            return;
+        }
+        if self.centroids.is_empty() && self.buffer.is_empty() {
+            *self = other;
+            return;
+        }
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.append(&mut other.centroids);
+        self.buffer.append(&mut other.buffer);
+        self.compress();
+    }
+
+    /// Folds the buffer into the centroids. Neighbours merge while the merged centroid spans
+    /// at most one unit of the `k1` scale, `COMPRESSION / 2π · asin(2q - 1)`.
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all = std::mem::take(&mut self.centroids);
+        all.append(&mut self.buffer);
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        let total: f64 = all.iter().map(|centroid| centroid.weight).sum();
+        let scale = |q: f64| COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin();
+        let unscale = |k: f64| {
+            let k = k.min(COMPRESSION / 4.0);
+            ((2.0 * std::f64::consts::PI * k / COMPRESSION).sin() + 1.0) / 2.0
+        };
+
+        let mut centroids = Vec::with_capacity(all.len().min(2 * COMPRESSION as usize));
+        let mut current = all[0];
+        let mut weight_before = 0.0;
+        let mut limit = unscale(scale(0.0) + 1.0);
+        for next in &all[1..] {
+            if (weight_before + current.weight + next.weight) / total <= limit {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / weight;
+                current.weight = weight;
+            } else {
+                weight_before += current.weight;
+                centroids.push(current);
+                limit = unscale(scale(weight_before / total) + 1.0);
+                current = *next;
+            }
+        }
+        centroids.push(current);
+        self.centroids = centroids;
+    }
+
+    /// The value `quantile` of the way through the measurements, interpolated between the
+    /// centres of the centroids around it, and towards the extremes past the outer centres.
+    /// Only the centroids count, so the digest must be compressed first.
+    fn quantile(&self, quantile: f64) -> Option<f64> {
+        let total: f64 = self.centroids.iter().map(|centroid| centroid.weight).sum();
+        let target = quantile * total;
+        let mut weight_before = 0.0;
+        let mut previous = None;
+        for centroid in &self.centroids {
+            let centre = weight_before + centroid.weight / 2.0;
+            if target < centre {
+                let (from, from_weight) = previous.unwrap_or((self.min, 0.0));
+                return Some(interpolate(
+                    from,
+                    from_weight,
+                    centroid.mean,
+                    centre,
+                    target,
+                ));
+            }
+            previous = Some((centroid.mean, centre));
+            weight_before += centroid.weight;
+        }
+        let (from, from_weight) = previous?;
+        Some(interpolate(from, from_weight, self.max, total, target))
+    }
+}
+
+/// The value at `target` on the line from `(from_weight, from)` to `(to_weight, to)`.
+fn interpolate(from: f64, from_weight: f64, to: f64, to_weight: f64, target: f64) -> f64 {
+    if to_weight <= from_weight {
+        return to;
+    }
+    from + (to - from) * ((target - from_weight) / (to_weight - from_weight)).clamp(0.0, 1.0)
+}
+
+/// Exact counts of one station's temperatures: a bucket per tenth of a degree the 1BRC
+/// contract allows, 1999 from -99.9 to 99.9, and one for whatever falls outside them.
+#[derive(Debug)]
+struct Histogram {
+    buckets: Box<[u64]>,
+    outside: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            buckets: vec![0; 2 * HOTTEST as usize + 1].into_boxed_slice(),
+            outside: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn add(&mut self, temperature: i32) {
+        match self.buckets.get_mut((temperature + HOTTEST) as usize) {
+            Some(count) if temperature >= -HOTTEST => *count += 1,
+            _ => self.outside += 1,
+        }
+    }
+
+    fn merge(&mut self, other: Histogram) {
+        for (count, other) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *count += other;
+        }
+        self.outside += other.outside;
+    }
+
+    /// One `temperature,count` row per bucket that counted anything, coldest first.
+    fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "temperature,count")?;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            if *count > 0 {
+                let temperature = Tenths(bucket as i64 - HOTTEST as i64);
+                writeln!(out, "{:.1},{}", temperature, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One digest and one histogram per station, as far as `Wanted` asked for them. Every worker
+/// fills one as it parses its chunks, and they are merged with the station totals.
+#[derive(Debug, Default)]
+pub struct Sketches {
+    wanted: Wanted,
+    digests: AHashMap<Key, Digest>,
+    histograms: AHashMap<Key, Histogram>,
+}
+
+impl Sketches {
+    pub fn new(wanted: Wanted) -> Sketches {
+        Sketches {
+            wanted,
+            ..Sketches::default()
+        }
+    }
+
+    /// Adds one temperature to `station_name`, which is already cut to what a `Key` keeps.
+    #[inline]
+    pub fn add(&mut self, station_name: &[u8], temperature: i32) {
+        if self.wanted.digests {
+            match self.digests.get_mut(station_name) {
+                Some(digest) => digest.add(temperature as f64),
+                None => self
+                    .digests
+                    .entry(Key::new(station_name))
+                    .or_default()
+                    .add(temperature as f64),
+            }
+        }
+        if self.wanted.histograms {
+            match self.histograms.get_mut(station_name) {
+                Some(histogram) => histogram.add(temperature),
+                None => self
+                    .histograms
+                    .entry(Key::new(station_name))
+                    .or_default()
+                    .add(temperature),
+            }
+        }
+    }
+
+    /// Folds what every digest still buffers into its centroids, which `percentile` reads.
+    pub fn compress(&mut self) {
+        self.digests.values_mut().for_each(Digest::compress);
+    }
+
+    /// Adds the sketches of another input.
+    pub fn merge(&mut self, other: Sketches) {
+        for (station_name, digest) in other.digests {
+            self.digests.entry(station_name).or_default().merge(digest);
+        }
+        for (station_name, histogram) in other.histograms {
+            self.histograms
+                .entry(station_name)
+                .or_default()
+                .merge(histogram);
+        }
+    }
+
+    /// `percentile` of the station's temperatures, rounded to a tenth; `None` for a station
+    /// no digest saw.
+    pub fn percentile(&self, station_name: &Key, percentile: Percentile) -> Option<Tenths> {
+        // `compress` left every digest compressed, and `merge` keeps them so.
+        let value = self
+            .digests
+            .get(station_name)?
+            .quantile(percentile.quantile())?;
+        Some(Tenths(value.round() as i64))
+    }
+
+    /// Writes the histogram of every station to `<station>.csv` in `dir`, creating it first.
+    /// Characters a file name cannot hold are percent-encoded, as is `%` itself, and a name
+    /// too long once encoded is cut and ends in a hash of the whole name instead.
+    pub fn write_histograms(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let mut outside = 0;
+        for (key, histogram) in &self.histograms {
+            let path = dir.join(file_name(&station_name(key)));
+            let mut out = BufWriter::new(File::create(path)?);
+            histogram.write_csv(&mut out)?;
+            out.into_inner()?.sync_all()?;
+            outside += histogram.outside;
+        }
+        if outside > 0 {
+            log::info!(
+                "{} measurements outside -99.9 to 99.9 are not in the histograms",
+                outside
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `<name>.csv` as a file name: path separators, the characters Windows reserves, control
+/// characters and `%` percent-encoded, and a leading `.` too, so no name is hidden or `..`.
+/// Encoding can triple a name, so one past `NAME_MAX` bytes is cut, never inside an escape,
+/// and told apart from the others it now shares a prefix with by a hash of the whole name.
+fn file_name(name: &str) -> String {
+    let mut file_name = String::with_capacity(name.len());
+    for (index, c) in name.chars().enumerate() {
+        match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '%' => {
+                file_name.push_str(&format!("%{:02X}", c as u32))
+            }
+            '.' if index == 0 => file_name.push_str("%2E"),
+            c if c.is_control() => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    file_name.push_str(&format!("%{:02X}", byte));
+                }
+            }
+            c => file_name.push(c),
+        }
+    }
+    if file_name.len() + ".csv".len() > NAME_MAX {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        let hash = format!("~{:016x}", hasher.finish());
+        let mut end = NAME_MAX - ".csv".len() - hash.len();
+        while !file_name.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some(escape) = file_name.as_bytes()[end - 2..end]
+            .iter()
+            .position(|&b| b == b'%')
+        {
+            end -= 2 - escape;
+        }
+        file_name.truncate(end);
+        file_name.push_str(&hash);
+    }
+    file_name + ".csv"
+}
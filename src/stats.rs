@@ -0,0 +1,123 @@
+#[cfg(feature = "cli")]
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "cli")]
+use crate::sketch::Percentile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Level {
+    /// Throughput and the split of worker time between phases
+    Basic,
+    /// Also hardware counters around the parse phase (`perf` feature, Linux only)
+    Detailed,
+}
+
+/// One item of `--stats`: a level of the worker breakdown, or a percentile for the report.
+#[cfg(feature = "cli")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request {
+    Level(Level),
+    Percentile(Percentile),
+}
+
+#[cfg(feature = "cli")]
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Request, String> {
+        match <Level as clap::ValueEnum>::from_str(text, true) {
+            Ok(level) => Ok(Request::Level(level)),
+            Err(_) if text.starts_with('p') => text.parse().map(Request::Percentile),
+            Err(_) => Err(format!(
+                "`{}` is not basic, detailed or a percentile like p99",
+                text
+            )),
+        }
+    }
+}
+
+/// Where the workers spent their time, summed over all of them.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub workers: usize,
+    pub bytes_read: u64,
+    pub phases: Vec<(&'static str, Duration)>,
+    /// Hardware counters summed over the parse phase of every worker.
+    pub counters: Vec<(&'static str, u64)>,
+}
+
+impl Stats {
+    /// Adds the bytes, phases and counters of a run over another input.
+    pub fn merge(&mut self, other: Stats) {
+        self.workers = self.workers.max(other.workers);
+        self.bytes_read += other.bytes_read;
+        for (name, time) in other.phases {
+            match self
+                .phases
+                .iter_mut()
+                .find(|(existing, _)| *existing == name)
+            {
+                Some((_, total)) => *total += time,
+                None => self.phases.push((name, time)),
+            }
+        }
+        for (name, count) in other.counters {
+            match self
+                .counters
+                .iter_mut()
+                .find(|(existing, _)| *existing == name)
+            {
+                Some((_, total)) => *total += count,
+                None => self.counters.push((name, count)),
+            }
+        }
+    }
+
+    pub fn print(&self, elapsed: Duration, rows: u64) {
+        let busy: Duration = self.phases.iter().map(|(_, time)| *time).sum();
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+
+        eprintln!(
+            "Stats: {} workers, {} bytes read, {:.1} MB/s, {:.1} M rows/s",
+            self.workers,
+            self.bytes_read,
+            self.bytes_read as f64 / seconds / 1e6,
+            rows as f64 / seconds / 1e6
+        );
+        for (name, time) in &self.phases {
+            eprintln!(
+                "  {:<10} {:>12.3?} ({:>5.1}% of worker time)",
+                name,
+                time,
+                percentage(*time, busy)
+            );
+        }
+
+        for (name, count) in &self.counters {
+            eprintln!("  {:<14} {:>16}", name, count);
+        }
+        let count = |wanted: &str| {
+            self.counters
+                .iter()
+                .find(|(name, _)| *name == wanted)
+                .map(|(_, count)| *count as f64)
+        };
+        if let (Some(cycles), Some(instructions)) = (count("cycles"), count("instructions")) {
+            eprintln!("  {:<14} {:>16.2}", "IPC", instructions / cycles.max(1.0));
+        }
+        if rows > 0 {
+            if let Some(cycles) = count("cycles") {
+                eprintln!("  {:<14} {:>16.1}", "cycles/row", cycles / rows as f64);
+            }
+        }
+    }
+}
+
+fn percentage(part: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        return 0.0;
+    }
+    part.as_secs_f64() * 100.0 / total.as_secs_f64()
+}
@@ -0,0 +1,206 @@
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+#[cfg(all(feature = "perf", target_os = "linux"))]
+use crate::perf;
+use crate::sharded::{self, ShardedMap};
+use crate::stats::Stats;
+use crate::{
+    heap, malformed, merge_into, merge_tree, process_lines, progress, signals, Aggregation, Config,
+    MapLayout, StationTemperatures, Worker, WorkerResult,
+};
+
+/// Aggregates an input that cannot seek, such as stdin.
+///
+/// The calling thread reads `config.chunk_size` bytes at a time, cuts every chunk after its
+/// last newline and carries the partial line over into the next one. Workers take chunks from
+/// a channel bounded to one per worker and hand the buffers back once parsed, so memory stays
+/// at a few chunks however long the stream runs and later chunks allocate nothing.
+/// `label` names the input in errors.
+pub fn aggregate(
+    mut reader: impl Read,
+    label: &Path,
+    config: &Config,
+    workers: usize,
+) -> Result<Aggregation> {
+    let chunk_size = config.chunk_size;
+    let (sender, receiver) = mpsc::sync_channel::<(u64, Vec<u8>)>(workers);
+    let receiver = Mutex::new(receiver);
+    let (recycle_sender, recycle_receiver) = mpsc::channel::<Vec<u8>>();
+    let parse_nanos = AtomicU64::new(0);
+    let aborted = AtomicBool::new(false);
+
+    let mut read_time = Duration::ZERO;
+    let mut bytes_read = 0;
+    let mut dispatched = 0;
+    let mut resume_offset = None;
+    let mut read_error = None;
+    let progress = progress::bar(config.progress, None);
+
+    let sharded = (config.map == MapLayout::Sharded).then(|| ShardedMap::new(workers));
+    let mut worker_maps = Vec::with_capacity(workers + 1);
+    let mut worker_sketches = Vec::with_capacity(workers);
+    let mut total_lines = 0;
+    let mut malformed = malformed::Report::default();
+    thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                let recycle_sender = recycle_sender.clone();
+                let (receiver, parse_nanos, aborted, sharded) =
+                    (&receiver, &parse_nanos, &aborted, &sharded);
+                scope.spawn(move || -> WorkerResult {
+                    let mut worker = Worker::new(config);
+                    let mut worker_temperatures = StationTemperatures::default();
+                    let mut total_lines = 0;
+                    loop {
+                        // Holding the lock only for `recv` lets the other workers parse meanwhile.
+                        let next = receiver.lock().unwrap().recv();
+                        let Ok((offset, chunk)) = next else {
+                            merge_into(&mut worker_temperatures, worker.table.into_map());
+                            return (
+                                worker_temperatures,
+                                total_lines,
+                                worker.malformed,
+                                worker.sketches,
+                            );
+                        };
+
+                        let parse_start = Instant::now();
+                        let phase = heap::enter(heap::Phase::Parse);
+                        #[cfg(all(feature = "perf", target_os = "linux"))]
+                        let counters = config.counters.then(perf::ParseCounters::start).flatten();
+                        let (station_temperatures, lines_count) =
+                            process_lines(&chunk, offset, config, &mut worker);
+                        #[cfg(all(feature = "perf", target_os = "linux"))]
+                        if let Some(counters) = counters {
+                            counters.stop();
+                        }
+                        drop(phase);
+                        if worker.malformed.aborted() {
+                            aborted.store(true, Ordering::Relaxed);
+                        }
+
+                        total_lines += lines_count as u64;
+                        parse_nanos
+                            .fetch_add(parse_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+                        let phase = heap::enter(heap::Phase::Merge);
+                        sharded::fold(
+                            sharded.as_ref(),
+                            &mut worker_temperatures,
+                            &mut worker.table,
+                            station_temperatures,
+                        );
+                        drop(phase);
+                        // The reader may be done already; the buffer is dropped then.
+                        let _ = recycle_sender.send(chunk);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        drop(recycle_sender);
+        let mut carry = Vec::new();
+        loop {
+            if signals::interrupted() || aborted.load(Ordering::Relaxed) {
+                resume_offset = Some(dispatched);
+                break;
+            }
+            signals::wait_while_paused();
+
+            let read_start = Instant::now();
+            let phase = heap::enter(heap::Phase::Read);
+            let mut chunk = recycle_receiver.try_recv().unwrap_or_default();
+            chunk.clear();
+            chunk.reserve(carry.len() + chunk_size);
+            chunk.append(&mut carry);
+            let bytes = match (&mut reader)
+                .take(chunk_size as u64)
+                .read_to_end(&mut chunk)
+            {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    read_error = Some(err);
+                    break;
+                }
+            };
+            drop(phase);
+            read_time += read_start.elapsed();
+            bytes_read += bytes as u64;
+            progress.inc(bytes as u64);
+
+            if bytes == 0 {
+                if !chunk.is_empty() {
+                    // The last line, which has no newline.
+                    let offset = dispatched;
+                    dispatched += chunk.len() as u64;
+                    sender.send((offset, chunk)).unwrap();
+                }
+                break;
+            }
+            match chunk.iter().rposition(|&b| b == b'\n') {
+                Some(last_newline) => carry.extend_from_slice(&chunk[last_newline + 1..]),
+                // Not even one whole line yet, keep reading.
+                None => {
+                    carry = chunk;
+                    continue;
+                }
+            }
+            chunk.truncate(chunk.len() - carry.len());
+            let offset = dispatched;
+            dispatched += chunk.len() as u64;
+            sender.send((offset, chunk)).unwrap();
+        }
+        // Closing the channel lets the workers finish once it is drained.
+        drop(sender);
+
+        for handle in handles {
+            let (worker_temperatures, lines_count, worker_malformed, sketches) =
+                handle.join().unwrap();
+            worker_maps.push(worker_temperatures);
+            worker_sketches.push(sketches);
+            total_lines += lines_count;
+            malformed.merge(worker_malformed, 0);
+        }
+    });
+    progress.finish();
+    if let Some(err) = read_error {
+        return Err(Error::read(label)(err));
+    }
+
+    let phase = heap::enter(heap::Phase::Merge);
+    worker_maps.extend(sharded.map(ShardedMap::into_map));
+    let station_temperatures = merge_tree(worker_maps);
+    let sketches = merge_tree(worker_sketches);
+    drop(phase);
+
+    let stats = Stats {
+        workers,
+        bytes_read,
+        phases: vec![
+            ("read", read_time),
+            (
+                "parse",
+                Duration::from_nanos(parse_nanos.load(Ordering::Relaxed)),
+            ),
+        ],
+        #[cfg(all(feature = "perf", target_os = "linux"))]
+        counters: perf::take_totals(),
+        #[cfg(not(all(feature = "perf", target_os = "linux")))]
+        counters: Vec::new(),
+    };
+
+    Ok(Aggregation {
+        station_temperatures,
+        total_lines,
+        stats,
+        resume_offset,
+        malformed,
+        sketches,
+    })
+}
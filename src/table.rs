@@ -0,0 +1,140 @@
+use std::hash::BuildHasher;
+
+use rustc_hash::FxBuildHasher;
+
+use crate::{merge_into, Key, StationTemperatures, WeatherData};
+
+/// Slots in a table: a power of two, so a hash maps to a slot with a mask.
+const SLOTS: usize = 1 << 14;
+/// Stations a table holds before new ones spill, which keeps probe runs short. Above the
+/// 10,000 stations the challenge allows.
+const MAX_ENTRIES: usize = SLOTS / 4 * 3;
+
+/// Where an entry is, with enough of it to skip most mismatches without touching the entry.
+#[derive(Clone, Copy)]
+struct Slot {
+    /// Never 0 for a used slot.
+    hash: u32,
+    len: u8,
+    index: u16,
+}
+
+const EMPTY: Slot = Slot {
+    hash: 0,
+    len: 0,
+    index: 0,
+};
+
+/// Linear-probing table for the parsers' hot loop, sized once for every station of a valid
+/// input: it never resizes and, as nothing is removed, needs no tombstones. Entries live in
+/// insertion order in a dense `Vec`; the slots only hold their cached hash, name length and
+/// index. Stations beyond `MAX_ENTRIES` go to a regular map, so an input breaking the
+/// station bound is slower but still correct.
+pub struct StationTable {
+    slots: Box<[Slot]>,
+    entries: Vec<(Key, WeatherData)>,
+    overflow: StationTemperatures,
+}
+
+impl StationTable {
+    pub fn new() -> StationTable {
+        StationTable {
+            slots: vec![EMPTY; SLOTS].into_boxed_slice(),
+            entries: Vec::with_capacity(1000),
+            overflow: StationTemperatures::default(),
+        }
+    }
+
+    /// Adds one temperature to `station_name`, which is already cut to what a `Key` keeps.
+    /// Returns the station's entry for `add_to`, `None` once it spilled.
+    #[inline(always)]
+    pub fn add(&mut self, station_name: &[u8], temperature: i32) -> Option<usize> {
+        let hash = FxBuildHasher.hash_one(station_name);
+        // The slot from the high bits, which the multiply in `FxHasher` mixes best.
+        let mut slot = (hash >> (64 - SLOTS.trailing_zeros())) as usize;
+        let tag = hash as u32 | 1;
+        loop {
+            let Slot { hash, len, index } = self.slots[slot];
+            if hash == 0 {
+                return self.insert(slot, tag, station_name, temperature);
+            }
+            if hash == tag && len as usize == station_name.len() {
+                let (key, data) = &mut self.entries[index as usize];
+                if key.as_bytes() == station_name {
+                    data.add_temperature(temperature);
+                    return Some(index as usize);
+                }
+            }
+            slot = (slot + 1) & (SLOTS - 1);
+        }
+    }
+
+    /// Adds one temperature to the station `add` returned `entry` for, without looking it up.
+    #[inline(always)]
+    pub fn add_to(&mut self, entry: usize, temperature: i32) {
+        self.entries[entry].1.add_temperature(temperature);
+    }
+
+    #[cold]
+    fn insert(
+        &mut self,
+        slot: usize,
+        tag: u32,
+        station_name: &[u8],
+        temperature: i32,
+    ) -> Option<usize> {
+        let data = WeatherData {
+            total_temperature: temperature as i64,
+            count: 1,
+            min_temperature: temperature,
+            max_temperature: temperature,
+            mean_temperature: 0,
+        };
+        if self.entries.len() == MAX_ENTRIES {
+            self.overflow
+                .entry(Key::new(station_name))
+                .and_modify(|existing| existing.add_temperature(temperature))
+                .or_insert(data);
+            return None;
+        }
+        self.slots[slot] = Slot {
+            hash: tag,
+            len: station_name.len() as u8,
+            index: self.entries.len() as u16,
+        };
+        self.entries.push((Key::new(station_name), data));
+        Some(self.entries.len() - 1)
+    }
+
+    /// Bytes the table takes, for `--mode map-bench`.
+    #[cfg(feature = "cli")]
+    pub fn memory(&self) -> usize {
+        std::mem::size_of_val(&*self.slots)
+            + self.entries.capacity() * std::mem::size_of::<(Key, WeatherData)>()
+    }
+
+    #[cfg(feature = "cli")]
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.overflow.len()
+    }
+
+    /// The totals so far, leaving the table empty but keeping its allocations.
+    pub fn take(&mut self) -> StationTemperatures {
+        if self.entries.is_empty() && self.overflow.is_empty() {
+            return StationTemperatures::default();
+        }
+        self.slots.fill(EMPTY);
+        let mut station_temperatures = self.entries.drain(..).collect();
+        merge_into(
+            &mut station_temperatures,
+            std::mem::take(&mut self.overflow),
+        );
+        station_temperatures
+    }
+
+    pub fn into_map(self) -> StationTemperatures {
+        let mut station_temperatures = self.entries.into_iter().collect();
+        merge_into(&mut station_temperatures, self.overflow);
+        station_temperatures
+    }
+}
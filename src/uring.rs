@@ -0,0 +1,311 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use io_uring::{opcode, types, IoUring};
+
+use crate::error::{Error, Result};
+use crate::malformed::Report;
+#[cfg(all(feature = "perf", target_os = "linux"))]
+use crate::perf;
+use crate::sharded::{self, ShardedMap};
+use crate::sketch::Sketches;
+use crate::stats::Stats;
+use crate::{
+    first_line, heap, log, merge_into, merge_tree, next_line_start, process_thread, progress,
+    read_at, signals, Aggregation, Config, MapLayout, StationTemperatures, Worker, WorkerResult,
+};
+
+/// The worker maps and sketches, and the lines and malformed lines of the chunks they parsed.
+type ReadResult = (Vec<StationTemperatures>, Vec<Sketches>, u64, Report);
+
+/// A chunk read into registered buffer `buffer`, which holds exactly the bytes read.
+struct Completed {
+    chunk_index: usize,
+    buffer: usize,
+    data: Vec<u8>,
+}
+
+/// Aggregates a file for `--engine uring`, `None` when the ring cannot be set up (an old
+/// kernel, a seccomp filter or too low a memlock limit) and the caller should read instead.
+///
+/// The calling thread keeps two reads per worker in flight, each into a buffer registered with
+/// the kernel up front, and hands every completed chunk to the workers over a channel. A
+/// worker returns the buffer once it parsed the chunk, and the buffer goes straight into the
+/// next read, so the only copies are the kernel's.
+pub fn aggregate(
+    file_path: &Path,
+    config: &Config,
+    workers: usize,
+    file_len: usize,
+) -> Result<Option<Aggregation>> {
+    let chunk_size = config.chunk_size;
+    let extra_buffer_size = config.extra_buffer_size();
+    let depth = workers * 2;
+
+    let file = File::open(file_path).map_err(Error::open(file_path))?;
+    let mut buffers = (0..depth)
+        .map(|_| Vec::with_capacity(chunk_size + extra_buffer_size))
+        .collect::<Vec<_>>();
+    let ring = match setup(depth, &mut buffers) {
+        Ok(ring) => ring,
+        Err(err) => {
+            log::info!(
+                "Unable to set up io_uring: {}, falling back to --engine read",
+                err
+            );
+            return Ok(None);
+        }
+    };
+    log::verbose!("io_uring: {} registered buffers", depth);
+
+    let mut first = Worker::new(config);
+    let (station_temperatures, first_lines) = first_line(file_path, file_len, config, &mut first)?;
+    let chunk_count = file_len.div_ceil(chunk_size);
+    let parse_nanos = AtomicU64::new(0);
+    let aborted = AtomicBool::new(false);
+    let sharded = (config.map == MapLayout::Sharded).then(|| ShardedMap::new(workers));
+    let progress = progress::bar(config.progress, Some(file_len as u64));
+
+    let mut wait_time = Duration::ZERO;
+    let mut bytes_read = 0;
+    let mut submitted = 0;
+    let (work_sender, work_receiver) = mpsc::channel::<Completed>();
+    let work_receiver = Mutex::new(work_receiver);
+    let (return_sender, return_receiver) = mpsc::channel::<(usize, Vec<u8>)>();
+
+    let read_result = thread::scope(|scope| -> io::Result<ReadResult> {
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let return_sender = return_sender.clone();
+            let (work_receiver, parse_nanos, progress) = (&work_receiver, &parse_nanos, &progress);
+            let (aborted, sharded) = (&aborted, &sharded);
+            handles.push(scope.spawn(move || -> WorkerResult {
+                let mut worker = Worker::new(config);
+                let mut worker_temperatures = StationTemperatures::default();
+                let mut total_lines = 0;
+                loop {
+                    let next = work_receiver.lock().unwrap().recv();
+                    let Ok(completed) = next else {
+                        merge_into(&mut worker_temperatures, worker.table.into_map());
+                        return (
+                            worker_temperatures,
+                            total_lines,
+                            worker.malformed,
+                            worker.sketches,
+                        );
+                    };
+
+                    let start = completed.chunk_index * chunk_size;
+                    let parse_start = Instant::now();
+                    let phase = heap::enter(heap::Phase::Parse);
+                    #[cfg(all(feature = "perf", target_os = "linux"))]
+                    let counters = config.counters.then(perf::ParseCounters::start).flatten();
+                    let (chunk_temperatures, lines_count) = process_thread(
+                        &completed.data,
+                        start as u64,
+                        chunk_size,
+                        extra_buffer_size,
+                        config,
+                        &mut worker,
+                    );
+                    #[cfg(all(feature = "perf", target_os = "linux"))]
+                    if let Some(counters) = counters {
+                        counters.stop();
+                    }
+                    drop(phase);
+                    if worker.malformed.aborted() {
+                        aborted.store(true, Ordering::Relaxed);
+                    }
+
+                    total_lines += lines_count as u64;
+                    parse_nanos
+                        .fetch_add(parse_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                    progress.inc(completed.data.len().min(chunk_size) as u64);
+                    let phase = heap::enter(heap::Phase::Merge);
+                    sharded::fold(
+                        sharded.as_ref(),
+                        &mut worker_temperatures,
+                        &mut worker.table,
+                        chunk_temperatures,
+                    );
+                    drop(phase);
+                    // The reader may be gone after an error; the buffer is dropped with it then.
+                    let _ = return_sender.send((completed.buffer, completed.data));
+                }
+            }));
+        }
+        drop(return_sender);
+        // Moved in, so the workers stop however this closure returns.
+        let work_sender = work_sender;
+
+        let mut free = buffers.drain(..).enumerate().collect::<Vec<_>>();
+        let mut in_flight: Vec<Option<(usize, Vec<u8>)>> = (0..depth).map(|_| None).collect();
+        let mut in_flight_count = 0;
+        let mut read = || -> io::Result<()> {
+            loop {
+                free.extend(return_receiver.try_iter());
+                let more = submitted < chunk_count
+                    && !signals::interrupted()
+                    && !aborted.load(Ordering::Relaxed);
+                if more && free.is_empty() && in_flight_count == 0 {
+                    // Every buffer is with a worker.
+                    free.extend(return_receiver.recv().ok());
+                }
+
+                while more && submitted < chunk_count {
+                    let Some((buffer, mut data)) = free.pop() else {
+                        break;
+                    };
+                    signals::wait_while_paused();
+                    let start = submitted * chunk_size;
+                    let len = (chunk_size + extra_buffer_size).min(file_len - start);
+                    data.clear();
+                    let entry = opcode::ReadFixed::new(
+                        types::Fd(file.as_raw_fd()),
+                        data.as_mut_ptr(),
+                        len as u32,
+                        buffer as u16,
+                    )
+                    .offset(start as u64)
+                    .build()
+                    .user_data(buffer as u64);
+                    // SAFETY: `data` is registered as buffer `buffer` and stays in `in_flight`,
+                    // untouched, until its completion is reaped.
+                    unsafe { ring.submission_shared().push(&entry) }
+                        .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+                    in_flight[buffer] = Some((submitted, data));
+                    in_flight_count += 1;
+                    submitted += 1;
+                }
+                if in_flight_count == 0 {
+                    return Ok(());
+                }
+
+                let wait_start = Instant::now();
+                let phase = heap::enter(heap::Phase::Read);
+                match ring.submit_and_wait(1) {
+                    Ok(_) => {}
+                    // A signal such as SIGINT, which the loop checks for above.
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+                // SAFETY: only this thread touches the completion queue.
+                let reaped = unsafe { ring.completion_shared() }
+                    .map(|completion| (completion.user_data() as usize, completion.result()))
+                    .collect::<Vec<_>>();
+                for (buffer, result) in reaped {
+                    let (chunk_index, mut data) = in_flight[buffer].take().unwrap();
+                    in_flight_count -= 1;
+                    if result < 0 {
+                        return Err(io::Error::from_raw_os_error(-result));
+                    }
+                    let start = chunk_index * chunk_size;
+                    let len = (chunk_size + extra_buffer_size).min(file_len - start);
+                    // SAFETY: the kernel wrote the first `result` bytes, and at most `len`, which
+                    // is within the capacity.
+                    unsafe { data.set_len(result as usize) };
+                    // A short read is rare on a regular file, but allowed; finish it directly.
+                    read_at(&file, &mut data, len, (start + result as usize) as u64)?;
+                    bytes_read += data.len() as u64;
+                    work_sender
+                        .send(Completed {
+                            chunk_index,
+                            buffer,
+                            data,
+                        })
+                        .unwrap();
+                }
+                drop(phase);
+                wait_time += wait_start.elapsed();
+            }
+        };
+        let result = read();
+        if result.is_err() {
+            // The kernel may still write into these, so they must never be reused.
+            in_flight.into_iter().flatten().for_each(std::mem::forget);
+        }
+        result?;
+        // Closing the channel lets the workers finish once it is drained.
+        drop(work_sender);
+
+        let mut worker_maps = Vec::with_capacity(workers + 1);
+        let mut worker_sketches = vec![first.sketches];
+        let mut total_lines = first_lines as u64;
+        let mut malformed = first.malformed;
+        for handle in handles {
+            let (worker_temperatures, lines_count, worker_malformed, sketches) =
+                handle.join().unwrap();
+            worker_maps.push(worker_temperatures);
+            worker_sketches.push(sketches);
+            total_lines += lines_count;
+            malformed.merge(worker_malformed, 0);
+        }
+        Ok((worker_maps, worker_sketches, total_lines, malformed))
+    });
+    progress.finish();
+    let (mut worker_maps, worker_sketches, total_lines, malformed) =
+        read_result.map_err(Error::read(file_path))?;
+
+    // Every submitted chunk completed, so everything before the next chunk's first line is done.
+    let resume_offset = match submitted * chunk_size {
+        done if done < file_len => {
+            next_line_start(file_path, done as u64).map_err(Error::read(file_path))?
+        }
+        _ => None,
+    };
+
+    let phase = heap::enter(heap::Phase::Merge);
+    worker_maps.push(station_temperatures);
+    worker_maps.extend(sharded.map(ShardedMap::into_map));
+    let station_temperatures = merge_tree(worker_maps);
+    let sketches = merge_tree(worker_sketches);
+    drop(phase);
+
+    let stats = Stats {
+        workers,
+        bytes_read,
+        phases: vec![
+            ("read", wait_time),
+            (
+                "parse",
+                Duration::from_nanos(parse_nanos.load(Ordering::Relaxed)),
+            ),
+        ],
+        #[cfg(all(feature = "perf", target_os = "linux"))]
+        counters: perf::take_totals(),
+        #[cfg(not(all(feature = "perf", target_os = "linux")))]
+        counters: Vec::new(),
+    };
+
+    Ok(Some(Aggregation {
+        station_temperatures,
+        total_lines,
+        stats,
+        resume_offset,
+        malformed,
+        sketches,
+    }))
+}
+
+/// A ring with room for `buffers.len()` reads and `buffers` registered with it.
+fn setup(depth: usize, buffers: &mut [Vec<u8>]) -> io::Result<IoUring> {
+    let ring = IoUring::new(depth.next_power_of_two() as u32)?;
+    let iovecs = buffers
+        .iter_mut()
+        .map(|buffer| libc::iovec {
+            iov_base: buffer.as_mut_ptr().cast(),
+            iov_len: buffer.capacity(),
+        })
+        .collect::<Vec<_>>();
+    // SAFETY: the buffers are neither moved nor freed while the ring uses them; their
+    // allocations stay put when the `Vec`s themselves are passed around, and are only ever
+    // cleared, never grown.
+    unsafe { ring.submitter().register_buffers(&iovecs)? };
+    Ok(ring)
+}
@@ -0,0 +1,20 @@
+use js_sys::{Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::process_bytes;
+
+/// `process_bytes` for JavaScript: `{station: {min, mean, max, count}}` for the bytes of an
+/// uploaded file, say, in degrees and sorted by station.
+#[wasm_bindgen(js_name = processBytes)]
+pub fn process_bytes_js(data: &[u8]) -> Result<Object, JsValue> {
+    let stations = Object::new();
+    for station in process_bytes(data).iter() {
+        let stats = Object::new();
+        Reflect::set(&stats, &"min".into(), &station.min.into())?;
+        Reflect::set(&stats, &"mean".into(), &station.mean.into())?;
+        Reflect::set(&stats, &"max".into(), &station.max.into())?;
+        Reflect::set(&stats, &"count".into(), &station.count.into())?;
+        Reflect::set(&stations, &station.name.into(), &stats)?;
+    }
+    Ok(stations)
+}
@@ -0,0 +1,38 @@
+//! The allocation counters are process-global, so this test gets a binary of its own.
+#![cfg(feature = "heap-profile")]
+
+use std::fs;
+
+use onebrc::heap;
+
+#[test]
+fn the_parse_phase_does_not_allocate() {
+    let path =
+        std::env::temp_dir().join(format!("onebrc-heap-profile-test-{}", std::process::id()));
+    let stations = ["Hamburg", "Bulawayo", "Palembang", "St. John's", "Cracow"];
+    let data: String = (0..100_000i64)
+        .map(|i| {
+            format!(
+                "{};{}.{}\n",
+                stations[i as usize % stations.len()],
+                i % 99 - 49,
+                i % 10
+            )
+        })
+        .collect();
+    fs::write(&path, data).unwrap();
+
+    let config = onebrc::Config::default();
+    // The first run warms up whatever is allocated once per process.
+    onebrc::aggregate_file(&path, &config).unwrap();
+    heap::take();
+    let aggregation = onebrc::aggregate_file(&path, &config).unwrap();
+    let counts = heap::take();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(aggregation.total_lines, 100_000);
+    let allocations = |name| counts.iter().find(|(phase, ..)| *phase == name).unwrap().1;
+    // The run itself allocates, so the allocator is counting; none of it is in the hot loop.
+    assert!(allocations("other") > 0);
+    assert_eq!(allocations("parse"), 0);
+}